@@ -1,6 +1,12 @@
-use anyhow::Result;
+use anyhow::{ensure, Context, Result};
+use http_server::{
+    Compression, EtagMode, Limits, MiddlewareConfig, MimeConfig, MimeTypes, Server, ServerBuilder,
+    Settings, SymlinkPolicy, Timeouts, TlsConfig, UnixSocketOptions,
+};
 use serde::Deserialize;
 use std::{
+    env::current_dir,
+    fmt::Debug,
     fs::File,
     io::Read,
     path::{Path, PathBuf},
@@ -9,14 +15,81 @@ use std::{
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub server: ServerConfig,
+    #[serde(default)]
+    pub mime: MimeConfig,
+    /// Middleware around request handling, outermost first.
+    #[serde(default)]
+    pub middleware: Vec<MiddlewareConfig>,
+    /// Listeners in addition to `server.address`.
+    #[serde(default, rename = "listener")]
+    pub listeners: Vec<ListenerConfig>,
+    pub tls: Option<TlsSection>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TlsSection {
+    #[serde(flatten)]
+    pub certs: TlsConfig,
+    /// Address of a listener redirecting all requests to HTTPS.
+    pub redirect: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct ServerConfig {
     pub content_root: Option<PathBuf>,
+    /// Either a socket address or `unix:` followed by a socket path.
     pub address: Option<String>,
+    /// Permissions of the socket file if listening on a Unix socket.
+    #[serde(default)]
+    pub socket: UnixSocketOptions,
+    /// Name of a socket passed by systemd to use instead of `address`.
+    pub fd_name: Option<String>,
+    /// Whether to use TLS if `[tls]` is configured, which is the default.
+    pub tls: Option<bool>,
     #[serde(default)]
     pub implicit_index: bool,
+    #[serde(default)]
+    pub directory_listing: bool,
+    #[serde(default)]
+    pub etag: EtagMode,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ListenerConfig {
+    pub address: String,
+    #[serde(default)]
+    pub socket: UnixSocketOptions,
+    pub fd_name: Option<String>,
+    pub tls: Option<bool>,
+    /// Serves this directory instead of `server.content_root`.
+    pub content_root: Option<PathBuf>,
+}
+
+impl ListenerConfig {
+    /// Returns the settings connections to this listener are served with,
+    /// or `None` if they are those of `server`.
+    pub fn settings(&self, server: &ServerBuilder) -> Result<Option<Settings>> {
+        let Some(content_root) = &self.content_root else {
+            return Ok(None);
+        };
+        ensure!(
+            content_root.is_dir(),
+            "content root {} of listener {} is not a directory",
+            content_root.display(),
+            self.address
+        );
+        Ok(Some(
+            server.clone().content_root(content_root).into_settings(),
+        ))
+    }
 }
 
 impl Config {
@@ -26,4 +99,130 @@ impl Config {
         f.read_to_string(&mut contents)?;
         Ok(toml::from_str(&contents)?)
     }
+
+    /// Creates a server builder with all settings of this config, loading
+    /// everything they refer to, but without middleware. Fails if the
+    /// content root is not a directory or a mime types file can not be read.
+    pub fn settings_builder(&self) -> Result<ServerBuilder> {
+        let content_root = match &self.server.content_root {
+            Some(v) => v.clone(),
+            None => current_dir()?,
+        };
+        ensure!(
+            content_root.is_dir(),
+            "content root {} is not a directory",
+            content_root.display()
+        );
+
+        let mime = MimeTypes::from_config(&self.mime).context("loading mime types")?;
+
+        Ok(Server::builder()
+            .content_root(content_root)
+            .implicit_index(self.server.implicit_index)
+            .directory_listing(self.server.directory_listing)
+            .etag(self.server.etag)
+            .limits(self.server.limits)
+            .timeouts(self.server.timeouts)
+            .compression(self.server.compression.clone())
+            .mime_types(mime)
+            .symlinks(self.server.symlinks))
+    }
+
+    /// Adds the configured middleware to `builder`.
+    pub fn add_middleware(&self, builder: ServerBuilder) -> ServerBuilder {
+        self.middleware
+            .iter()
+            .fold(builder, |b, m| b.layer(m.build()))
+    }
+
+    /// Lists all settings which differ between `self` and `other`.
+    pub fn diff(&self, other: &Config) -> Vec<Change> {
+        let mut changes = vec![];
+        let mut cmp = |key, old: &dyn Debug, new: &dyn Debug| {
+            let (old, new) = (format!("{old:?}"), format!("{new:?}"));
+            if old != new {
+                changes.push(Change { key, old, new });
+            }
+        };
+
+        let (old, new) = (&self.server, &other.server);
+        cmp("server.content_root", &old.content_root, &new.content_root);
+        cmp("server.address", &old.address, &new.address);
+        cmp("server.socket", &old.socket, &new.socket);
+        cmp("server.fd_name", &old.fd_name, &new.fd_name);
+        cmp("server.tls", &old.tls, &new.tls);
+        cmp(
+            "server.implicit_index",
+            &old.implicit_index,
+            &new.implicit_index,
+        );
+        cmp(
+            "server.directory_listing",
+            &old.directory_listing,
+            &new.directory_listing,
+        );
+        cmp("server.etag", &old.etag, &new.etag);
+        cmp("server.limits", &old.limits, &new.limits);
+        cmp("server.timeouts", &old.timeouts, &new.timeouts);
+        cmp("server.compression", &old.compression, &new.compression);
+        cmp("server.symlinks", &old.symlinks, &new.symlinks);
+        cmp("mime", &self.mime, &other.mime);
+        cmp("listener", &self.listeners, &other.listeners);
+        let (old, new) = (self.tls.as_ref(), other.tls.as_ref());
+        cmp("tls", &old.map(|t| &t.certs), &new.map(|t| &t.certs));
+        cmp(
+            "tls.redirect",
+            &old.map(|t| &t.redirect),
+            &new.map(|t| &t.redirect),
+        );
+
+        // The debug output hides passwords, so changes to them have to be
+        // detected by comparing the values.
+        if self.middleware != other.middleware {
+            changes.push(Change {
+                key: "middleware",
+                old: format!("{:?}", self.middleware),
+                new: format!("{:?}", other.middleware),
+            });
+        }
+
+        changes
+    }
+}
+
+/// A setting which differs between two configs, with both values in their
+/// debug representation.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Change {
+    pub key: &'static str,
+    pub old: String,
+    pub new: String,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn config_diff() {
+        let old: Config = toml::from_str("[server]\nimplicit_index = true").unwrap();
+        let new: Config = toml::from_str(
+            "[server]\nimplicit_index = true\ndirectory_listing = true\n[mime]\nsniff = true",
+        )
+        .unwrap();
+
+        assert_eq!(old.diff(&old), []);
+
+        let changes = old.diff(&new);
+        let keys: Vec<_> = changes.iter().map(|c| c.key).collect();
+        assert_eq!(keys, ["server.directory_listing", "mime"]);
+        assert_eq!(
+            changes[0],
+            Change {
+                key: "server.directory_listing",
+                old: "false".into(),
+                new: "true".into(),
+            }
+        );
+    }
 }
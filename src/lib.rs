@@ -0,0 +1,29 @@
+//! A small HTTP/1.1 server for static files, which can be embedded into
+//! other tokio applications.
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:8080").await?;
+//!
+//! http_server::Server::builder()
+//!     .content_root("public")
+//!     .implicit_index(true)
+//!     .build(listener)
+//!     .listen(async {
+//!         let _ = tokio::signal::ctrl_c().await;
+//!     })
+//!     .await
+//! # }
+//! ```
+
+mod server;
+
+pub use server::{
+    handler_fn, middleware_fn, AccessLog, BasicAuth, Body, BoxBody, BoxFuture, Certificates,
+    ClientAuth, ClientAuthConfig, ClientAuthMode, ClientCert, Compression, ContentLength, EtagMode,
+    FileServer, Form, Handler, HandlerFn, HeaderMap, HttpsRedirect, Limits, Listener, Method,
+    Middleware, MiddlewareConfig, MiddlewareFn, MimeConfig, MimeTypes, Next, NoOp, Peer, Request,
+    RequestError, RequireClientCert, Response, ResponseBuilder, Router, Server, ServerBuilder,
+    SetHeaders, Settings, SettingsHandle, SniConfig, StatusCode, SymlinkPolicy, Text, Timeouts,
+    Tls, TlsConfig, UnixSocketOptions, Uri, Version,
+};
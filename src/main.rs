@@ -1,11 +1,13 @@
 mod config;
-mod server;
 
-use anyhow::Result;
-use config::Config;
-use server::Server;
-use std::env::{self, current_dir};
-use tracing::{debug, info};
+use anyhow::{Context, Result};
+use config::{Config, ListenerConfig};
+use http_server::{
+    Certificates, ClientAuth, HttpsRedirect, Listener, SettingsHandle, Tls, UnixSocketOptions,
+};
+use std::{env, future::pending, net::SocketAddr};
+use tokio::{signal, sync::watch};
+use tracing::{debug, error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -20,61 +22,285 @@ async fn main() -> Result<()> {
     let cfg = Config::parse(&cfg_path)?;
     debug!("config: {cfg:?}");
 
-    let addr = &cfg.server.address.unwrap_or_else(|| "0.0.0.0:80".into());
-
-    let listener = tokio::net::TcpListener::bind(addr).await?;
+    // The redirect server answers every request with a redirect, so it
+    // runs none of the middleware.
+    let settings_builder = cfg.settings_builder()?;
+    let builder = cfg.add_middleware(settings_builder.clone());
+    let tls = match &cfg.tls {
+        Some(t) => Some(Tls::from_config(&t.certs).context("loading TLS certificates")?),
+        None => None,
+    };
+    let with_tls = |listener: Listener, enabled: Option<bool>| match &tls {
+        Some(tls) if enabled.unwrap_or(true) => listener.tls(tls.clone()),
+        _ => listener,
+    };
 
-    info!("Listening on {addr} ...");
+    let mut listeners = open_listeners(&cfg).await?.into_iter();
+    let primary = with_tls(listeners.next().unwrap(), cfg.server.tls);
+    let mut server = builder.clone().build(primary);
+    let mut handles = Handles {
+        settings: vec![server.settings_handle()],
+        listeners: vec![],
+        tls: tls.clone(),
+    };
+    for (l, listener) in cfg.listeners.iter().zip(listeners) {
+        let settings = l.settings(&builder)?;
+        let own = settings.is_some();
+        let handle = server.add_listener(with_tls(listener, l.tls), settings);
+        if own {
+            handles.listeners.push((l.clone(), handle));
+        }
+    }
 
-    let content_dir = match cfg.server.content_root {
-        Some(v) => v,
-        None => current_dir()?,
+    let redirect = match cfg.tls.as_ref().and_then(|t| t.redirect.as_deref()) {
+        Some(addr) => {
+            let listener = Listener::bind(addr, &UnixSocketOptions::default())
+                .await
+                .with_context(|| format!("binding {addr}"))?;
+            info!("Redirecting to HTTPS on {addr} ...");
+            let redirect = settings_builder
+                .handler(HttpsRedirect::new(https_port(&cfg)))
+                .build(listener);
+            handles.settings.push(redirect.settings_handle());
+            Some(redirect)
+        }
+        None => None,
     };
 
-    Server::new(listener, content_dir, cfg.server.implicit_index)
-        .listen()
-        .await
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(cfg_path, cfg, handles));
+    #[cfg(not(unix))]
+    drop(handles);
+
+    match redirect {
+        Some(redirect) => {
+            // Both servers shut down on the same signal, which drops the
+            // sender.
+            let (stop_tx, stop_rx) = watch::channel(());
+            tokio::spawn(async move {
+                shutdown_signal().await;
+                drop(stop_tx);
+            });
+            let stopped = |mut rx: watch::Receiver<()>| async move {
+                let _ = rx.changed().await;
+            };
+            tokio::try_join!(
+                server.listen(stopped(stop_rx.clone())),
+                redirect.listen(stopped(stop_rx)),
+            )?;
+        }
+        None => server.listen(shutdown_signal()).await?,
+    }
+
+    Ok(())
+}
+
+/// Everything a config reload replaces in the running servers.
+#[cfg_attr(not(unix), allow(dead_code))]
+struct Handles {
+    /// Settings of all listeners without settings of their own.
+    settings: Vec<SettingsHandle>,
+    /// Listeners with settings of their own.
+    listeners: Vec<(ListenerConfig, SettingsHandle)>,
+    tls: Option<Tls>,
+}
+
+/// Returns the port HTTPS is served on, which is that of `server.address`
+/// if it is a socket address.
+fn https_port(cfg: &Config) -> u16 {
+    cfg.server
+        .address
+        .as_deref()
+        .and_then(|a| a.parse::<SocketAddr>().ok())
+        .map_or(443, |a| a.port())
+}
+
+/// Opens the listeners for `server.address` followed by those for all
+/// `[[listener]]` tables. If started by systemd socket activation, they
+/// take the sockets passed by name if they have an `fd_name`, and the
+/// remaining sockets in order otherwise. Only listeners left without one
+/// bind their address.
+async fn open_listeners(cfg: &Config) -> Result<Vec<Listener>> {
+    let primary = (
+        cfg.server.address.as_deref().unwrap_or("0.0.0.0:80"),
+        &cfg.server.socket,
+        cfg.server.fd_name.as_deref(),
+    );
+    let configured: Vec<_> = std::iter::once(primary)
+        .chain(
+            cfg.listeners
+                .iter()
+                .map(|l| (l.address.as_str(), &l.socket, l.fd_name.as_deref())),
+        )
+        .collect();
+
+    #[cfg(unix)]
+    let mut inherited = Listener::inherited().context("adopting sockets passed by systemd")?;
+    #[cfg(not(unix))]
+    let mut inherited: Vec<(String, Listener)> = vec![];
+
+    let mut listeners: Vec<Option<Listener>> = configured.iter().map(|_| None).collect();
+    if !inherited.is_empty() {
+        for (i, &(addr, _, fd_name)) in configured.iter().enumerate() {
+            let Some(name) = fd_name else { continue };
+            let pos = inherited
+                .iter()
+                .position(|(n, _)| n == name)
+                .with_context(|| format!("systemd passed no socket named {name} for {addr}"))?;
+            let (_, listener) = inherited.remove(pos);
+            info!("Listening on socket {name} passed by systemd for {addr} ...");
+            listeners[i] = Some(listener);
+        }
+        for (i, &(addr, _, fd_name)) in configured.iter().enumerate() {
+            if fd_name.is_some() || inherited.is_empty() {
+                continue;
+            }
+            let (name, listener) = inherited.remove(0);
+            info!("Listening on socket {name} passed by systemd for {addr} ...");
+            listeners[i] = Some(listener);
+        }
+        for (name, _) in &inherited {
+            warn!("Closing socket {name} passed by systemd, no listener is configured for it");
+        }
+    }
+
+    let mut res = vec![];
+    for (listener, (addr, socket, _)) in listeners.into_iter().zip(configured) {
+        let listener = match listener {
+            Some(l) => l,
+            None => {
+                let l = Listener::bind(addr, socket)
+                    .await
+                    .with_context(|| format!("binding {addr}"))?;
+                info!("Listening on {addr} ...");
+                l
+            }
+        };
+        res.push(listener);
+    }
+
+    Ok(res)
 }
 
-#[cfg(test)]
-mod test {
-    use crate::server::Server;
-    use std::time::Duration;
-    use tokio::{fs::File, io::AsyncReadExt, time::sleep};
+/// Re-reads the config file on every SIGHUP and applies it to all requests
+/// started afterwards, and the TLS certificates to connections accepted
+/// afterwards. If the file can not be parsed or is invalid, the current
+/// config is kept.
+#[cfg(unix)]
+async fn reload_on_hangup(path: String, mut current: Config, handles: Handles) {
+    let mut hangup = match signal::unix::signal(signal::unix::SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(err) => {
+            error!("Failed listening for SIGHUP: {err}");
+            return;
+        }
+    };
 
-    #[tokio::test]
-    async fn integration_test() {
-        let addr = "127.0.0.1:18735";
+    while hangup.recv().await.is_some() {
+        info!("Reloading config from {path} ...");
 
-        tokio::spawn(async move {
-            let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-            Server::new(listener, "content", true).listen().await
+        let reloaded = Config::parse(&path).and_then(|cfg| {
+            let builder = cfg.settings_builder()?;
+            let listener_settings = handles
+                .listeners
+                .iter()
+                .map(|(l, _)| l.settings(&builder))
+                .collect::<Result<Vec<_>>>()?;
+            let certs = match &cfg.tls {
+                Some(t) if handles.tls.is_some() => {
+                    let certs =
+                        Certificates::from_config(&t.certs).context("loading TLS certificates")?;
+                    let client_auth = t
+                        .certs
+                        .client_auth
+                        .as_ref()
+                        .map(ClientAuth::from_config)
+                        .transpose()
+                        .context("loading TLS client CA certificates")?;
+                    Some((certs, client_auth))
+                }
+                _ => None,
+            };
+            Ok((builder, listener_settings, certs, cfg))
         });
-        sleep(Duration::from_millis(100)).await;
-
-        let res = reqwest::get(format!("http://{addr}")).await.unwrap();
-        assert!(res.status().is_success());
-        assert_eq!(
-            res.headers()
-                .get("Content-Type")
-                .map(|v| v.to_str().unwrap()),
-            Some("text/html; charset=utf-8")
-        );
-
-        let mut index_contents = String::new();
-        File::open("content/index.html")
-            .await
-            .unwrap()
-            .read_to_string(&mut index_contents)
-            .await
-            .unwrap();
-
-        assert_eq!(res.text().await.unwrap(), index_contents);
-
-        let res = reqwest::get(format!("http://{addr}/does-not-exist"))
-            .await
-            .unwrap();
-        assert_eq!(res.status().as_u16(), 404);
-        assert_eq!(res.text().await.unwrap(), "Not Found");
+        let (new_settings, listener_settings, certs, cfg) = match reloaded {
+            Ok(v) => v,
+            Err(err) => {
+                error!("Failed reloading config, keeping the current one: {err:#}");
+                continue;
+            }
+        };
+
+        let changes = current.diff(&cfg);
+        for change in &changes {
+            info!(
+                key = change.key,
+                old = change.old,
+                new = change.new,
+                "config changed"
+            );
+        }
+        for key in [
+            "server.address",
+            "server.socket",
+            "server.fd_name",
+            "server.tls",
+            "middleware",
+            "listener",
+            "tls.redirect",
+        ] {
+            if changes.iter().any(|c| c.key == key) {
+                warn!("Changing {key} requires a restart");
+            }
+        }
+        if current.tls.is_some() != cfg.tls.is_some() {
+            warn!("Enabling or disabling TLS requires a restart");
+        }
+
+        let new_settings = new_settings.into_settings();
+        for handle in &handles.settings {
+            handle.store(new_settings.clone());
+        }
+        for ((_, handle), new) in handles.listeners.iter().zip(listener_settings) {
+            if let Some(new) = new {
+                handle.store(new);
+            }
+        }
+        if let (Some(tls), Some((certs, client_auth))) = (&handles.tls, certs) {
+            tls.store(certs);
+            tls.store_client_auth(client_auth);
+        }
+        current = cfg;
+        info!("Config reloaded, {} settings changed", changes.len());
+    }
+}
+
+/// Completes once SIGINT or SIGTERM has been received.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = signal::ctrl_c().await {
+            error!("Failed listening for SIGINT: {err}");
+            pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(err) => {
+                error!("Failed listening for SIGTERM: {err}");
+                pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
     }
 }
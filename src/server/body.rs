@@ -0,0 +1,478 @@
+use super::{
+    error::RequestError,
+    parser::{parse_field_line, read_line},
+    request::{HeaderMap, Version},
+};
+use std::{fmt, io, time::Duration};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt},
+    sync::oneshot,
+    time::{timeout_at, Instant},
+};
+
+/// Maximum length of a chunk size line or trailer field line.
+const MAX_LINE_LEN: usize = 8 * 1024;
+
+/// Chunk size lines and trailer fields of a body may take up this many
+/// bytes in addition to its maximum size.
+const MAX_FRAMING_LEN: usize = 64 * 1024;
+
+/// Maximum number of bytes handed out by a single call to `Body::chunk`.
+const MAX_READ_LEN: usize = 64 * 1024;
+
+/// The buffered read half of a connection request bodies are read from.
+pub(crate) type Reader<'c> = dyn AsyncBufRead + Send + Sync + Unpin + 'c;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Framing {
+    None,
+    Length(u64),
+    Chunked,
+}
+
+impl Framing {
+    /// Determines how the body of a request is delimited as specified in
+    /// RFC 9112, Section 6.3. Requests which could be interpreted in more
+    /// than one way are rejected to prevent request smuggling.
+    pub fn from_header(version: Version, header: &HeaderMap) -> Result<Self, RequestError> {
+        let content_length = header.get("content-length");
+
+        if let Some(te) = header.get("transfer-encoding") {
+            if content_length.is_some() {
+                return Err(RequestError::BadRequest(
+                    "both Content-Length and Transfer-Encoding are set",
+                ));
+            }
+            if version < Version::Http11 {
+                return Err(RequestError::BadRequest(
+                    "Transfer-Encoding is not allowed in HTTP/1.0 requests",
+                ));
+            }
+
+            let codings: Vec<_> = te
+                .iter()
+                .flat_map(|v| v.split(','))
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .collect();
+
+            return match codings.as_slice() {
+                [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(Self::Chunked),
+                [.., last] if last.eq_ignore_ascii_case("chunked") => {
+                    Err(RequestError::NotImplemented("unsupported transfer coding"))
+                }
+                _ => Err(RequestError::BadRequest(
+                    "chunked must be the final transfer coding",
+                )),
+            };
+        }
+
+        let Some(content_length) = content_length else {
+            return Ok(Self::None);
+        };
+
+        let mut length = None;
+        for v in content_length.iter().flat_map(|v| v.split(',')) {
+            let v = v.trim();
+            if v.is_empty() || !v.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(RequestError::BadRequest("invalid Content-Length"));
+            }
+            let v: u64 = v
+                .parse()
+                .map_err(|_| RequestError::BadRequest("invalid Content-Length"))?;
+            if length.is_some_and(|l| l != v) {
+                return Err(RequestError::BadRequest(
+                    "conflicting Content-Length values",
+                ));
+            }
+            length = Some(v);
+        }
+
+        match length {
+            None | Some(0) => Ok(Self::None),
+            Some(length) => Ok(Self::Length(length)),
+        }
+    }
+}
+
+enum State {
+    Done,
+    Length(u64),
+    ChunkSize,
+    ChunkData(u64),
+    ChunkDataEnd,
+}
+
+/// The body of a request, which is read from the connection on demand.
+///
+/// Body data is consumed chunk by chunk using `Body::chunk`, or collected
+/// at once using `Body::bytes`. Reading more than the configured maximum
+/// body size fails with `RequestError::PayloadTooLarge`.
+pub struct Body<'c> {
+    r: Option<&'c mut Reader<'c>>,
+    state: State,
+    limit: usize,
+    /// Body data read so far, which counts toward `limit`.
+    read: usize,
+    /// Chunk size lines and trailer fields read so far.
+    framing: usize,
+    trailer: Option<HeaderMap>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    continue_tx: Option<oneshot::Sender<()>>,
+}
+
+impl<'c> Body<'c> {
+    pub(crate) fn empty() -> Self {
+        Self {
+            r: None,
+            state: State::Done,
+            limit: 0,
+            read: 0,
+            framing: 0,
+            trailer: None,
+            timeout: None,
+            deadline: None,
+            continue_tx: None,
+        }
+    }
+
+    pub(crate) fn new(
+        r: &'c mut Reader<'c>,
+        framing: Framing,
+        limit: usize,
+    ) -> Result<Self, RequestError> {
+        let state = match framing {
+            Framing::None => return Ok(Self::empty()),
+            Framing::Length(length) if length > limit as u64 => {
+                return Err(RequestError::PayloadTooLarge)
+            }
+            Framing::Length(length) => State::Length(length),
+            Framing::Chunked => State::ChunkSize,
+        };
+
+        Ok(Self {
+            r: Some(r),
+            state,
+            limit,
+            read: 0,
+            framing: 0,
+            trailer: None,
+            timeout: None,
+            deadline: None,
+            continue_tx: None,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Limits the time reading the whole body may take, starting with the
+    /// first read. Exceeding it fails with `RequestError::Timeout`.
+    pub(crate) fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    /// Returns a receiver which completes when the body is first read, so
+    /// the client is only asked to send it by `100 Continue` if it is
+    /// actually needed.
+    pub(crate) fn expect_continue(&mut self) -> oneshot::Receiver<()> {
+        let (tx, rx) = oneshot::channel();
+        self.continue_tx = Some(tx);
+        rx
+    }
+
+    /// Reads the next piece of the body. Returns `None` once the whole body
+    /// has been read.
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, RequestError> {
+        if let Some(tx) = self.continue_tx.take() {
+            let _ = tx.send(());
+        }
+        let Some(timeout) = self.timeout else {
+            return self.read_chunk().await;
+        };
+        let deadline = *self
+            .deadline
+            .get_or_insert_with(|| Instant::now() + timeout);
+
+        timeout_at(deadline, self.read_chunk())
+            .await
+            .map_err(|_| RequestError::Timeout)?
+    }
+
+    async fn read_chunk(&mut self) -> Result<Option<Vec<u8>>, RequestError> {
+        let Some(r) = self.r.as_mut() else {
+            return Ok(None);
+        };
+
+        loop {
+            match self.state {
+                State::Done | State::Length(0) => {
+                    self.state = State::Done;
+                    return Ok(None);
+                }
+                State::Length(remaining) => {
+                    let data = read_data(r, remaining).await?;
+                    self.state = State::Length(remaining - data.len() as u64);
+                    self.read += data.len();
+                    return Ok(Some(data));
+                }
+                State::ChunkSize => {
+                    let mut line = vec![];
+                    read_line(r, &mut line, MAX_LINE_LEN, line_too_long()).await?;
+                    // Framing is limited separately, so a chunked body may be
+                    // as large as one with a Content-Length.
+                    let framing_limit = self.limit.saturating_add(MAX_FRAMING_LEN);
+                    self.framing += line.len();
+                    if self.framing > framing_limit {
+                        return Err(RequestError::PayloadTooLarge);
+                    }
+
+                    let size = parse_chunk_size(&line)?;
+                    if size == 0 {
+                        let trailer = read_trailer(r, &mut self.framing, framing_limit).await?;
+                        self.trailer = Some(trailer);
+                        self.state = State::Done;
+                        return Ok(None);
+                    }
+                    if self.read as u64 + size > self.limit as u64 {
+                        return Err(RequestError::PayloadTooLarge);
+                    }
+
+                    self.state = State::ChunkData(size);
+                }
+                State::ChunkData(remaining) => {
+                    let data = read_data(r, remaining).await?;
+                    let remaining = remaining - data.len() as u64;
+                    self.state = match remaining {
+                        0 => State::ChunkDataEnd,
+                        _ => State::ChunkData(remaining),
+                    };
+                    self.read += data.len();
+                    return Ok(Some(data));
+                }
+                State::ChunkDataEnd => {
+                    let mut line = vec![];
+                    read_line(r, &mut line, MAX_LINE_LEN, line_too_long()).await?;
+                    if !line.is_empty() {
+                        return Err(RequestError::BadRequest("missing CRLF after chunk data"));
+                    }
+                    self.state = State::ChunkSize;
+                }
+            }
+        }
+    }
+
+    /// Reads the remaining body into memory.
+    pub async fn bytes(&mut self) -> Result<Vec<u8>, RequestError> {
+        let mut res = vec![];
+        while let Some(chunk) = self.chunk().await? {
+            res.extend_from_slice(&chunk);
+        }
+        Ok(res)
+    }
+
+    /// Reads and discards the remaining body so that the next request can be
+    /// read from the connection.
+    pub async fn drain(&mut self) -> Result<(), RequestError> {
+        while self.chunk().await?.is_some() {}
+        Ok(())
+    }
+
+    /// Returns the trailer fields of a chunked body once it has been read
+    /// completely.
+    pub fn trailer(&self) -> Option<&HeaderMap> {
+        self.trailer.as_ref()
+    }
+}
+
+impl fmt::Debug for Body<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Body")
+            .field("read", &self.read)
+            .field("limit", &self.limit)
+            .field("trailer", &self.trailer)
+            .finish_non_exhaustive()
+    }
+}
+
+async fn read_data<R>(r: &mut R, remaining: u64) -> Result<Vec<u8>, RequestError>
+where
+    R: AsyncBufRead + Unpin + ?Sized,
+{
+    let buf = r.fill_buf().await?;
+    if buf.is_empty() {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    let n = buf.len().min(MAX_READ_LEN).min(remaining as usize);
+    let data = buf[..n].to_vec();
+    r.consume(n);
+
+    Ok(data)
+}
+
+fn line_too_long() -> RequestError {
+    RequestError::BadRequest("chunk or trailer line too long")
+}
+
+fn parse_chunk_size(line: &[u8]) -> Result<u64, RequestError> {
+    let size = line.split(|b| *b == b';').next().unwrap_or_default();
+    let size = std::str::from_utf8(size)
+        .map_err(|_| RequestError::BadRequest("invalid chunk size"))?
+        .trim();
+
+    // `from_str_radix` would accept a sign, which `1*HEXDIG` doesn't.
+    if size.is_empty() || size.len() > 16 || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(RequestError::BadRequest("invalid chunk size"));
+    }
+
+    u64::from_str_radix(size, 16).map_err(|_| RequestError::BadRequest("invalid chunk size"))
+}
+
+async fn read_trailer<R>(
+    r: &mut R,
+    read: &mut usize,
+    limit: usize,
+) -> Result<HeaderMap, RequestError>
+where
+    R: AsyncBufRead + Unpin + ?Sized,
+{
+    let mut m = HeaderMap::new();
+    let mut line = vec![];
+
+    loop {
+        line.clear();
+        *read += read_line(r, &mut line, MAX_LINE_LEN, line_too_long()).await?;
+        if *read > limit {
+            return Err(RequestError::PayloadTooLarge);
+        }
+
+        if line.is_empty() {
+            break;
+        }
+
+        let (key, value) = parse_field_line(&line)?;
+        m.insert(key, value);
+    }
+
+    Ok(m)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut m = HeaderMap::new();
+        for (k, v) in pairs {
+            m.insert(k, *v);
+        }
+        m
+    }
+
+    #[test]
+    fn framing() {
+        let v = Version::Http11;
+        assert_eq!(
+            Framing::from_header(v, &header(&[])).unwrap(),
+            Framing::None
+        );
+        assert_eq!(
+            Framing::from_header(v, &header(&[("content-length", "12")])).unwrap(),
+            Framing::Length(12)
+        );
+        assert_eq!(
+            Framing::from_header(v, &header(&[("content-length", "12, 12")])).unwrap(),
+            Framing::Length(12)
+        );
+        assert_eq!(
+            Framing::from_header(v, &header(&[("transfer-encoding", "Chunked")])).unwrap(),
+            Framing::Chunked
+        );
+
+        for h in [
+            header(&[("content-length", "12"), ("content-length", "13")]),
+            header(&[("content-length", "+12")]),
+            header(&[("content-length", "12"), ("transfer-encoding", "chunked")]),
+            header(&[("transfer-encoding", "chunked, gzip")]),
+        ] {
+            assert!(matches!(
+                Framing::from_header(v, &h),
+                Err(RequestError::BadRequest(_))
+            ));
+        }
+
+        assert!(matches!(
+            Framing::from_header(v, &header(&[("transfer-encoding", "gzip, chunked")])),
+            Err(RequestError::NotImplemented(_))
+        ));
+        assert!(matches!(
+            Framing::from_header(
+                Version::Http10,
+                &header(&[("transfer-encoding", "chunked")])
+            ),
+            Err(RequestError::BadRequest(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn content_length_body() {
+        let mut r: &[u8] = b"hello worldGET / HTTP/1.1\r\n";
+        let mut body = Body::new(&mut r, Framing::Length(11), 1024).unwrap();
+        assert_eq!(body.bytes().await.unwrap(), b"hello world");
+        assert_eq!(r, b"GET / HTTP/1.1\r\n");
+
+        let mut r: &[u8] = b"hello world";
+        assert!(matches!(
+            Body::new(&mut r, Framing::Length(11), 10),
+            Err(RequestError::PayloadTooLarge)
+        ));
+    }
+
+    #[tokio::test]
+    async fn chunked_body() {
+        let mut r: &[u8] =
+            b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Checksum: abc\r\n\r\nGET / HTTP/1.1\r\n";
+        let mut body = Body::new(&mut r, Framing::Chunked, 1024).unwrap();
+        assert_eq!(body.chunk().await.unwrap().unwrap(), b"hello");
+        assert_eq!(body.chunk().await.unwrap().unwrap(), b" world");
+        assert_eq!(body.chunk().await.unwrap(), None);
+        assert_eq!(
+            body.trailer().unwrap().get("x-checksum").unwrap(),
+            &vec!["abc".to_string()]
+        );
+        drop(body);
+        assert_eq!(r, b"GET / HTTP/1.1\r\n");
+
+        // Only data counts toward the limit, like with Content-Length.
+        let mut r: &[u8] = b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-A: b\r\n\r\n";
+        let mut body = Body::new(&mut r, Framing::Chunked, 11).unwrap();
+        assert_eq!(body.bytes().await.unwrap(), b"hello world");
+
+        let mut r: &[u8] = b"5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n";
+        let mut body = Body::new(&mut r, Framing::Chunked, 8).unwrap();
+        assert!(matches!(
+            body.bytes().await,
+            Err(RequestError::PayloadTooLarge)
+        ));
+
+        let mut r: &[u8] = b"5\r\nhelloX\r\n0\r\n\r\n";
+        let mut body = Body::new(&mut r, Framing::Chunked, 1024).unwrap();
+        assert!(matches!(
+            body.bytes().await,
+            Err(RequestError::BadRequest(_))
+        ));
+
+        for size in ["zz", "+5", "0x5"] {
+            let chunk = format!("{size}\r\nhello\r\n0\r\n\r\n");
+            let mut r = chunk.as_bytes();
+            let mut body = Body::new(&mut r, Framing::Chunked, 1024).unwrap();
+            assert!(
+                matches!(body.bytes().await, Err(RequestError::BadRequest(_))),
+                "{size}"
+            );
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use super::negotiate::parse_quality_list;
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use serde::Deserialize;
+use std::pin::Pin;
+use tokio::io::{AsyncBufRead, AsyncRead};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct Compression {
+    /// Compress responses on the fly if the client accepts it.
+    pub enabled: bool,
+    /// Minimum size of a response body in bytes to be compressed.
+    pub min_size: u64,
+    /// Media types which are compressed. Entries like `text/*` match all
+    /// subtypes.
+    pub types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size: 1024,
+            types: [
+                "text/*",
+                "application/javascript",
+                "application/json",
+                "application/xml",
+                "application/wasm",
+                "image/svg+xml",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+impl Compression {
+    /// Checks if a body of the given media type and size may be compressed.
+    /// Responses for which this is true vary by `Accept-Encoding`.
+    pub fn applies(&self, mime: Option<&str>, size: u64) -> bool {
+        let Some(mime) = mime else {
+            return false;
+        };
+        let essence = mime.split(';').next().unwrap_or_default().trim();
+
+        self.enabled
+            && size >= self.min_size
+            && self.types.iter().any(|t| match t.strip_suffix("/*") {
+                Some(ty) => essence
+                    .split_once('/')
+                    .is_some_and(|(v, _)| v.eq_ignore_ascii_case(ty)),
+                None => essence.eq_ignore_ascii_case(t),
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl Encoding {
+    /// All supported encodings in the order the server prefers them.
+    pub const ALL: [Encoding; 3] = [Self::Brotli, Self::Zstd, Self::Gzip];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// File extension of precompressed sidecar files.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Zstd => "zst",
+            Self::Gzip => "gz",
+        }
+    }
+
+    fn matches(&self, coding: &str) -> bool {
+        coding.eq_ignore_ascii_case(self.as_str())
+            || (*self == Self::Gzip && coding.eq_ignore_ascii_case("x-gzip"))
+    }
+
+    /// Picks the encoding out of `available` with the highest weight in the
+    /// `Accept-Encoding` values. Ties favour the server's preference.
+    /// Returns `None` if no encoding is acceptable or the client explicitly
+    /// prefers `identity`.
+    pub fn negotiate<S: AsRef<str>>(
+        accept_encoding: Option<&[S]>,
+        available: &[Encoding],
+    ) -> Option<Self> {
+        let items = parse_quality_list(accept_encoding?);
+        let weight = |coding: &dyn Fn(&str) -> bool| {
+            items
+                .iter()
+                .find(|i| coding(i.value))
+                .or_else(|| items.iter().find(|i| i.value == "*"))
+                .map(|i| i.q)
+        };
+
+        let mut best: Option<(Self, f32)> = None;
+        for enc in Self::ALL.into_iter().filter(|e| available.contains(e)) {
+            let q = weight(&|v| enc.matches(v)).unwrap_or(0.0);
+            if q > 0.0 && best.is_none_or(|(_, b)| q > b) {
+                best = Some((enc, q));
+            }
+        }
+
+        // Identity is acceptable by default, so it only wins if the client
+        // asks for it explicitly with a higher weight.
+        let identity = items
+            .iter()
+            .find(|i| i.value.eq_ignore_ascii_case("identity"))
+            .map_or(0.0, |i| i.q);
+
+        best.filter(|(_, q)| *q >= identity).map(|(enc, _)| enc)
+    }
+
+    /// Wraps `r` into a reader producing the encoded stream.
+    pub fn encode<R>(&self, r: R) -> Pin<Box<dyn AsyncRead + Send>>
+    where
+        R: AsyncBufRead + Send + 'static,
+    {
+        match self {
+            Self::Brotli => Box::pin(BrotliEncoder::new(r)),
+            Self::Zstd => Box::pin(ZstdEncoder::new(r)),
+            Self::Gzip => Box::pin(GzipEncoder::new(r)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn negotiate_encoding() {
+        let pick = |v: &str| Encoding::negotiate(Some(&[v]), &Encoding::ALL);
+
+        assert_eq!(Encoding::negotiate::<&str>(None, &Encoding::ALL), None);
+        assert_eq!(pick("gzip, deflate, br, zstd"), Some(Encoding::Brotli));
+        assert_eq!(pick("gzip;q=1, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(pick("x-gzip"), Some(Encoding::Gzip));
+        assert_eq!(pick("*"), Some(Encoding::Brotli));
+        assert_eq!(pick("*, br;q=0"), Some(Encoding::Zstd));
+        assert_eq!(pick("gzip;q=0.5, identity"), None);
+        assert_eq!(pick("deflate"), None);
+        assert_eq!(pick(""), None);
+
+        let gzip_only = Encoding::negotiate(Some(&["br, gzip;q=0.5"]), &[Encoding::Gzip]);
+        assert_eq!(gzip_only, Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn compressible() {
+        let c = Compression::default();
+        assert!(c.applies(Some("text/html; charset=utf-8"), 2048));
+        assert!(c.applies(Some("application/javascript; charset=utf-8"), 2048));
+        assert!(!c.applies(Some("text/html"), 100));
+        assert!(!c.applies(Some("image/png"), 2048));
+        assert!(!c.applies(None, 2048));
+    }
+}
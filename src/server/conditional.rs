@@ -0,0 +1,313 @@
+use super::request::{HeaderMap, Method};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    io,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs::File, io::AsyncReadExt};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EtagMode {
+    /// Derive the ETag from the file size and modification time.
+    #[default]
+    Metadata,
+    /// Derive the ETag from a SHA-256 hash of the file contents.
+    Hash,
+}
+
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Precondition {
+    Proceed,
+    NotModified,
+    Failed,
+}
+
+impl Validators {
+    pub async fn new(path: &Path, meta: &Metadata, mode: EtagMode) -> io::Result<Self> {
+        // HTTP dates have a resolution of one second, so the modification
+        // time is truncated to make comparisons with client dates work.
+        let last_modified = meta.modified().ok().map(truncate_to_secs);
+
+        let etag = match mode {
+            EtagMode::Metadata => {
+                let mtime = last_modified
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .unwrap_or_default();
+                format!("\"{:x}-{:x}\"", meta.len(), mtime.as_secs())
+            }
+            EtagMode::Hash => format!("\"{}\"", file_digest(path, meta).await?),
+        };
+
+        Ok(Self {
+            etag,
+            last_modified,
+        })
+    }
+
+    /// Derives the entity tag of a content-coded representation, so that
+    /// it does not match the tag of the identity representation.
+    pub fn encoded(self, coding: &str) -> Self {
+        let etag = match self.etag.strip_suffix('"') {
+            Some(tag) => format!("{tag}-{coding}\""),
+            None => self.etag,
+        };
+        Self { etag, ..self }
+    }
+
+    pub fn last_modified_header(&self) -> Option<String> {
+        self.last_modified.map(httpdate::fmt_http_date)
+    }
+
+    /// Evaluates the conditional request headers in the order given by
+    /// RFC 9110, Section 13.2.2.
+    pub fn evaluate(&self, method: &Method, header: &HeaderMap) -> Precondition {
+        let is_get_or_head = matches!(method, Method::Get | Method::Head);
+
+        if let Some(if_match) = header.get("if-match") {
+            if !matches_any(if_match, &self.etag, true) {
+                return Precondition::Failed;
+            }
+        } else if let Some(since) = header_date(header, "if-unmodified-since") {
+            if self.last_modified.is_some_and(|lm| lm > since) {
+                return Precondition::Failed;
+            }
+        }
+
+        if let Some(if_none_match) = header.get("if-none-match") {
+            if matches_any(if_none_match, &self.etag, false) {
+                return if is_get_or_head {
+                    Precondition::NotModified
+                } else {
+                    Precondition::Failed
+                };
+            }
+        } else if is_get_or_head {
+            if let Some(since) = header_date(header, "if-modified-since") {
+                if self.last_modified.is_some_and(|lm| lm <= since) {
+                    return Precondition::NotModified;
+                }
+            }
+        }
+
+        Precondition::Proceed
+    }
+
+    /// Evaluates `If-Range` and returns whether a `Range` header should be
+    /// honoured. Only strong entity tags and exact dates can match.
+    pub fn if_range(&self, header: &HeaderMap) -> bool {
+        let Some(values) = header.get("if-range") else {
+            return true;
+        };
+        let Some(value) = values.first().map(|v| v.trim()) else {
+            return true;
+        };
+
+        if value.starts_with('"') || value.starts_with("W/") {
+            return value == self.etag;
+        }
+
+        match (httpdate::parse_http_date(value), self.last_modified) {
+            (Ok(date), Some(last_modified)) => date == last_modified,
+            _ => false,
+        }
+    }
+}
+
+fn truncate_to_secs(t: SystemTime) -> SystemTime {
+    match t.duration_since(UNIX_EPOCH) {
+        Ok(d) => UNIX_EPOCH + Duration::from_secs(d.as_secs()),
+        Err(_) => t,
+    }
+}
+
+fn header_date(header: &HeaderMap, key: &str) -> Option<SystemTime> {
+    let values = header.get(key)?;
+    httpdate::parse_http_date(values.first()?).ok()
+}
+
+/// Checks if any of the entity tags in the given header values matches
+/// `etag`. Weak tags never match when `strong` is set.
+fn matches_any(values: &[String], etag: &str, strong: bool) -> bool {
+    values
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .any(|tag| {
+            if tag == "*" {
+                return true;
+            }
+            match tag.strip_prefix("W/") {
+                Some(_) if strong => false,
+                Some(weak) => weak == etag,
+                None => tag == etag,
+            }
+        })
+}
+
+/// Digests of the files hashed for `EtagMode::Hash`. A file is only read
+/// again once its size or modification time changes.
+static DIGESTS: LazyLock<Mutex<HashMap<PathBuf, CachedDigest>>> = LazyLock::new(Default::default);
+
+/// Bounds the memory used by `DIGESTS`, which is cleared when full.
+const MAX_DIGESTS: usize = 4096;
+
+struct CachedDigest {
+    len: u64,
+    modified: SystemTime,
+    digest: String,
+}
+
+async fn file_digest(path: &Path, meta: &Metadata) -> io::Result<String> {
+    // Without a modification time, changes can't be detected.
+    let Ok(modified) = meta.modified() else {
+        return hash_file(path).await;
+    };
+    let len = meta.len();
+
+    if let Some(cached) = DIGESTS.lock().unwrap().get(path) {
+        if cached.len == len && cached.modified == modified {
+            return Ok(cached.digest.clone());
+        }
+    }
+
+    let digest = hash_file(path).await?;
+    let mut digests = DIGESTS.lock().unwrap();
+    if digests.len() >= MAX_DIGESTS {
+        digests.clear();
+    }
+    digests.insert(
+        path.to_path_buf(),
+        CachedDigest {
+            len,
+            modified,
+            digest: digest.clone(),
+        },
+    );
+    Ok(digest)
+}
+
+async fn hash_file(path: &Path) -> io::Result<String> {
+    let mut f = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let n = f.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn validators() -> Validators {
+        Validators {
+            etag: "\"abc\"".into(),
+            last_modified: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+        }
+    }
+
+    fn header(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut m = HeaderMap::new();
+        for (k, v) in pairs {
+            m.insert(k, *v);
+        }
+        m
+    }
+
+    #[test]
+    fn if_none_match() {
+        let v = validators();
+        let h = header(&[("if-none-match", "\"xyz\", W/\"abc\"")]);
+        assert_eq!(v.evaluate(&Method::Get, &h), Precondition::NotModified);
+        assert_eq!(v.evaluate(&Method::Put, &h), Precondition::Failed);
+
+        let h = header(&[("if-none-match", "\"xyz\"")]);
+        assert_eq!(v.evaluate(&Method::Get, &h), Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let v = validators();
+        let lm = v.last_modified_header().unwrap();
+        let h = header(&[("if-modified-since", &lm)]);
+        assert_eq!(v.evaluate(&Method::Get, &h), Precondition::NotModified);
+
+        let h = header(&[("if-modified-since", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(v.evaluate(&Method::Get, &h), Precondition::Proceed);
+
+        // If-None-Match takes precedence over If-Modified-Since.
+        let h = header(&[("if-modified-since", &lm), ("if-none-match", "\"xyz\"")]);
+        assert_eq!(v.evaluate(&Method::Get, &h), Precondition::Proceed);
+    }
+
+    #[test]
+    fn if_match() {
+        let v = validators();
+        let h = header(&[("if-match", "\"abc\"")]);
+        assert_eq!(v.evaluate(&Method::Get, &h), Precondition::Proceed);
+
+        let h = header(&[("if-match", "W/\"abc\"")]);
+        assert_eq!(v.evaluate(&Method::Get, &h), Precondition::Failed);
+
+        let h = header(&[("if-unmodified-since", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(v.evaluate(&Method::Get, &h), Precondition::Failed);
+    }
+
+    #[test]
+    fn if_range() {
+        let v = validators();
+        assert!(v.if_range(&header(&[])));
+        assert!(v.if_range(&header(&[("if-range", "\"abc\"")])));
+        assert!(!v.if_range(&header(&[("if-range", "W/\"abc\"")])));
+        assert!(!v.if_range(&header(&[("if-range", "\"xyz\"")])));
+
+        let lm = v.last_modified_header().unwrap();
+        assert!(v.if_range(&header(&[("if-range", &lm)])));
+        assert!(!v.if_range(&header(&[("if-range", "Sun, 06 Nov 1994 08:49:37 GMT")])));
+    }
+
+    #[tokio::test]
+    async fn cached_digest() {
+        let path = std::env::temp_dir().join(format!("etag-digest-{}", std::process::id()));
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let write = |content: &str, mtime| {
+            std::fs::write(&path, content).unwrap();
+            let f = std::fs::File::options().write(true).open(&path).unwrap();
+            f.set_modified(mtime).unwrap();
+            f.metadata().unwrap()
+        };
+
+        let meta = write("aaaa", mtime);
+        let digest = file_digest(&path, &meta).await.unwrap();
+
+        // The same size and modification time are taken as unchanged.
+        let meta = write("bbbb", mtime);
+        assert_eq!(file_digest(&path, &meta).await.unwrap(), digest);
+
+        let meta = write("bbbb", mtime + Duration::from_secs(1));
+        assert_ne!(file_digest(&path, &meta).await.unwrap(), digest);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,196 +1,228 @@
 use super::{
-    request::{HeaderMap, Method, Request},
+    error::RequestError,
+    handler::Handler,
+    listener::Peer,
+    parser::RequestParser,
+    request::{Method, Request, Version},
     response::ResponseBuilder,
+    timeout::TimeoutWriter,
+    tls::ClientCert,
+    Settings, SettingsHandle,
 };
-use crate::server::statuscode::StatusCode;
-use anyhow::Result;
-use std::{
-    fs::Metadata,
-    io::{self, ErrorKind},
-    net::SocketAddr,
-    path::{Path, PathBuf},
-};
+use std::{future::pending, sync::Arc, time::Duration};
 use tokio::{
-    fs::File,
-    io::{AsyncBufReadExt, BufReader},
-    net::TcpStream,
+    io::{
+        self, sink, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+        ReadHalf, WriteHalf,
+    },
+    sync::watch,
+    time::{timeout, timeout_at, Instant},
 };
-use tracing::{debug, error, info};
-
-pub struct Conn {
-    stream: TcpStream,
-    content_root: PathBuf,
-    implicit_index: bool,
+use tracing::debug;
+
+const LINGER_TIMEOUT: Duration = Duration::from_secs(1);
+const LINGER_MAX_BYTES: u64 = 64 * 1024;
+
+/// A connection over any transport, e.g. a TCP or Unix socket, a TLS
+/// stream or an in-memory pipe.
+pub struct Conn<S> {
+    reader: BufReader<ReadHalf<S>>,
+    writer: TimeoutWriter<WriteHalf<S>>,
+    settings_handle: SettingsHandle,
+    settings: Arc<Settings>,
+    handler: Arc<dyn Handler>,
+    peer: Peer,
+    client_cert: Option<Arc<ClientCert>>,
+    accepted: Instant,
+    shutdown: watch::Receiver<bool>,
 }
 
-impl Conn {
+impl<S> Conn<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync,
+{
     pub fn new(
-        stream: TcpStream,
-        _: SocketAddr,
-        content_root: PathBuf,
-        implicit_index: bool,
+        stream: S,
+        peer: Peer,
+        settings_handle: SettingsHandle,
+        handler: Arc<dyn Handler>,
+        shutdown: watch::Receiver<bool>,
     ) -> Self {
+        let (reader, writer) = io::split(stream);
+        let settings = settings_handle.load();
         Self {
-            stream,
-            content_root,
-            implicit_index,
+            reader: BufReader::new(reader),
+            writer: TimeoutWriter::new(writer, settings.timeouts.write_timeout),
+            settings_handle,
+            settings,
+            handler,
+            peer,
+            client_cert: None,
+            accepted: Instant::now(),
+            shutdown,
         }
     }
 
-    pub async fn serve(&mut self) -> Result<()> {
-        loop {
-            let r = BufReader::new(&mut self.stream);
-            let Some(req) = RequestParser::new(r).parse().await? else {
-                break;
-            };
+    /// Sets the verified certificate the client presented in the TLS
+    /// handshake.
+    pub(crate) fn client_cert(mut self, client_cert: Option<Arc<ClientCert>>) -> Self {
+        self.client_cert = client_cert;
+        self
+    }
 
-            info!("-> {} {}", req.method, req.path.to_string_lossy());
+    pub async fn serve(&mut self) -> io::Result<()> {
+        let mut served = 0;
 
-            if !matches!(req.method, Method::Get) {
-                ResponseBuilder::new()
-                    .status_code(StatusCode::MethodNotAllowed)
-                    .send(&mut self.stream)
-                    .await?;
-                continue;
-            }
+        loop {
+            // The first request has to arrive within the header timeout
+            // after the connection was accepted, later ones start with the
+            // first byte received after an idle period.
+            let idle_deadline = if served == 0 {
+                self.accepted + self.settings.timeouts.header_read_timeout
+            } else {
+                Instant::now() + self.settings.timeouts.keep_alive_idle_timeout
+            };
 
-            let mut path = self
-                .content_root
-                .join(req.path.strip_prefix("/").unwrap_or(&req.path));
-            if self.implicit_index && path.is_dir() {
-                path = path.join("index.html");
+            let idle = tokio::select! {
+                biased;
+                res = timeout_at(idle_deadline, self.reader.fill_buf()) => res,
+                _ = self.shutdown.wait_for(|v| *v) => {
+                    debug!("closing idle connection on shutdown");
+                    break;
+                }
+            };
+            match idle {
+                Err(_) => {
+                    debug!("closing idle connection");
+                    break;
+                }
+                Ok(Err(err)) => return Err(err),
+                Ok(Ok([])) => break,
+                Ok(Ok(_)) => {}
             }
 
-            debug!("trying to serve file {}", path.to_string_lossy());
-
-            match open_file(&path).await {
-                Ok((f, meta)) => {
-                    let mut b = ResponseBuilder::new();
-
-                    if let Some(mime) = mime_from_path(&path) {
-                        b = b.add_header("content-type", mime);
-                    }
+            // Every request is served with the settings current when it
+            // starts, so reloads apply to persistent connections as well.
+            self.settings = self.settings_handle.load();
+            let timeouts = self.settings.timeouts;
+            self.writer.set_timeout(timeouts.write_timeout);
+            let header_deadline = if served == 0 {
+                idle_deadline
+            } else {
+                Instant::now() + timeouts.header_read_timeout
+            };
 
-                    b.body(f, meta.len() as usize)
-                        .send(&mut self.stream)
-                        .await?
-                }
-                Err(err) if err.kind() == ErrorKind::NotFound => {
-                    ResponseBuilder::new()
-                        .status_code(StatusCode::NotFound)
-                        .send(&mut self.stream)
-                        .await?
-                }
+            let parser = RequestParser::new(&mut self.reader, self.settings.clone());
+            let parsed = timeout_at(header_deadline, parser.parse())
+                .await
+                .unwrap_or(Err(RequestError::Timeout));
+            let mut req = match parsed {
+                Ok(Some(req)) => req,
+                Ok(None) => break,
                 Err(err) => {
-                    error!("opening file for response: {}", err);
-                    ResponseBuilder::new()
-                        .status_code(StatusCode::InternalServerError)
-                        .send(&mut self.stream)
-                        .await?
+                    debug!("rejecting request: {err}");
+                    if let Some(status) = err.status_code() {
+                        ResponseBuilder::new()
+                            .status_code(status)
+                            .keep_alive(false)
+                            .send(&mut self.writer)
+                            .await?;
+                        self.linger().await;
+                    }
+                    break;
                 }
             };
-        }
+            req.body.set_timeout(timeouts.body_read_timeout);
+            req.peer = self.peer.clone();
+            req.client_cert = self.client_cert.clone();
 
-        Ok(())
-    }
-}
-
-struct RequestParser<R> {
-    r: R,
-    buf: String,
-}
+            debug!("-> {} {}", req.method, req.uri);
 
-impl<R> RequestParser<R>
-where
-    R: AsyncBufReadExt,
-    R: Unpin,
-{
-    pub fn new(r: R) -> Self {
-        Self {
-            r,
-            buf: String::new(),
-        }
-    }
-
-    pub async fn parse(&mut self) -> Result<Option<Request>> {
-        let Some((proto, path, method)) = self.parse_head().await? else {
-            return Ok(None);
-        };
-
-        let header = self.parse_header().await?;
-
-        Ok(Some(Request {
-            method,
-            proto,
-            path,
-            header,
-            body: None,
-        }))
-    }
-
-    pub async fn parse_head(&mut self) -> Result<Option<(String, PathBuf, Method)>> {
-        self.buf.clear();
-        self.r.read_line(&mut self.buf).await?;
-
-        if self.buf.trim().is_empty() {
-            return Ok(None);
-        }
-
-        let mut split = self.buf.split(' ');
-        let method = split
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("invalid header: no method"))?
-            .into();
-        let path = split
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("invalid header: no path"))?
-            .into();
-        let proto = split
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("invalid header: no proto"))?
-            .to_string();
-
-        Ok(Some((proto, path, method)))
-    }
-
-    pub async fn parse_header(&mut self) -> Result<HeaderMap> {
-        let mut m = HeaderMap::new();
-
-        loop {
-            self.buf.clear();
-            self.r.read_line(&mut self.buf).await?;
-
-            if self.buf.trim().is_empty() {
-                break;
+            served += 1;
+            if served >= self.settings.limits.max_requests_per_connection || *self.shutdown.borrow()
+            {
+                req.keep_alive = false;
             }
 
-            let Some((key, value)) = self.buf.split_once(':') else {
-                anyhow::bail!("invalid header: no key-value pair");
+            let mut continue_rx = (!req.body.is_empty() && expects_continue(&req))
+                .then(|| req.body.expect_continue());
+            let mut res = {
+                let handle = self.handler.handle(&mut req);
+                tokio::pin!(handle);
+                loop {
+                    let read = async {
+                        match &mut continue_rx {
+                            Some(rx) => rx.await.is_ok(),
+                            None => pending().await,
+                        }
+                    };
+                    tokio::select! {
+                        res = &mut handle => break res,
+                        read = read => {
+                            continue_rx = None;
+                            if read {
+                                self.writer
+                                    .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                                    .await?;
+                            }
+                        }
+                    }
+                }
             };
+            // A client still waiting for `100 Continue` may never send the
+            // body, so it can't be drained.
+            if continue_rx.is_some() {
+                req.keep_alive = false;
+            }
+            res = res.version(req.version);
+            req.keep_alive &= res.keeps_alive();
+            if req.method == Method::Head {
+                res = res.omit_body(true);
+            }
+            res.keep_alive(req.keep_alive)
+                .keep_alive_timeout(timeouts.keep_alive_idle_timeout)
+                .send(&mut self.writer)
+                .await?;
+
+            if !req.keep_alive || *self.shutdown.borrow() {
+                let unread_body = !req.body.is_empty();
+                drop(req);
+                if unread_body {
+                    self.linger().await;
+                }
+                break;
+            }
 
-            m.insert(key, value.trim());
+            // Any unread body has to be consumed before the next request can
+            // be read from the connection.
+            if let Err(err) = req.body.drain().await {
+                debug!("failed reading request body: {err}");
+                break;
+            }
         }
 
-        Ok(m)
+        self.writer.shutdown().await?;
+
+        Ok(())
     }
-}
 
-async fn open_file(path: &Path) -> io::Result<(File, Metadata)> {
-    let f = File::open(path).await?;
-    let meta = f.metadata().await?;
-    Ok((f, meta))
+    /// Closes the write half and discards what the client is still sending
+    /// for a short time. Dropping the connection while unread data is
+    /// pending would otherwise reset it, possibly before the client has
+    /// read the error response.
+    async fn linger(&mut self) {
+        if self.writer.shutdown().await.is_err() {
+            return;
+        }
+        let mut r = (&mut self.reader).take(LINGER_MAX_BYTES);
+        let _ = timeout(LINGER_TIMEOUT, io::copy(&mut r, &mut sink())).await;
+    }
 }
 
-fn mime_from_path(path: &Path) -> Option<&'static str> {
-    let ext = path.extension()?.to_str()?;
-    match ext {
-        "html" | "htm" => Some("text/html; charset=utf-8"),
-        "txt" => Some("text/plain"),
-        "css" => Some("text/css"),
-        "js" => Some("application/javascript; charset=utf-8"),
-        "png" => Some("image/png"),
-        "jpg" | "jpeg" => Some("image/jpeg"),
-        "webp" => Some("image/webp"),
-        _ => None,
-    }
+fn expects_continue(req: &Request<'_>) -> bool {
+    req.version >= Version::Http11
+        && req
+            .header
+            .get("expect")
+            .is_some_and(|v| v.iter().any(|v| v.eq_ignore_ascii_case("100-continue")))
 }
@@ -0,0 +1,57 @@
+use super::statuscode::StatusCode;
+use std::{fmt, io};
+
+/// Errors which occur while reading a request from a connection. Most of
+/// them are answered with an error status before the connection is closed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum RequestError {
+    BadRequest(&'static str),
+    UriTooLong,
+    RequestHeaderFieldsTooLarge,
+    PayloadTooLarge,
+    Timeout,
+    HttpVersionNotSupported,
+    NotImplemented(&'static str),
+    Io(io::Error),
+}
+
+impl RequestError {
+    /// Returns the status code to answer the request with, or `None` if the
+    /// connection should be closed without a response.
+    pub fn status_code(&self) -> Option<StatusCode> {
+        match self {
+            Self::BadRequest(_) => Some(StatusCode::BadRequest),
+            Self::UriTooLong => Some(StatusCode::UriTooLong),
+            Self::RequestHeaderFieldsTooLarge => Some(StatusCode::RequestHeaderFieldsTooLarge),
+            Self::PayloadTooLarge => Some(StatusCode::PayloadTooLarge),
+            Self::Timeout => Some(StatusCode::RequestTimeout),
+            Self::HttpVersionNotSupported => Some(StatusCode::HttpVersionNotSupported),
+            Self::NotImplemented(_) => Some(StatusCode::NotImplemented),
+            Self::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest(reason) => write!(f, "bad request: {reason}"),
+            Self::UriTooLong => write!(f, "request line too long"),
+            Self::RequestHeaderFieldsTooLarge => write!(f, "header section too large"),
+            Self::PayloadTooLarge => write!(f, "payload too large"),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::HttpVersionNotSupported => write!(f, "http version not supported"),
+            Self::NotImplemented(reason) => write!(f, "not implemented: {reason}"),
+            Self::Io(err) => write!(f, "io: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl From<io::Error> for RequestError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
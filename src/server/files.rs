@@ -0,0 +1,358 @@
+use super::{
+    compress::Encoding,
+    conditional::{Precondition, Validators},
+    handler::{BoxFuture, Handler, Response},
+    listing::{Listing, Sort},
+    mime::{sniff, SNIFF_LEN},
+    negotiate::preferred_media_type,
+    range::{content_range, parse_range, Ranges},
+    readers::{ContentLength, NoOp, Text},
+    request::{Method, Request, Version},
+    resolve::{resolve, SymlinkPolicy},
+    response::ResponseBuilder,
+    statuscode::StatusCode,
+    Settings,
+};
+use std::{
+    collections::hash_map::RandomState,
+    fs::Metadata,
+    hash::{BuildHasher, Hasher},
+    io::{self, ErrorKind, SeekFrom},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, BufReader},
+};
+use tracing::{debug, error};
+
+/// Serves the files below the content root of the settings a request is
+/// served with. Only `GET` and `HEAD` requests are supported.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileServer;
+
+impl Handler for FileServer {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            match serve(req).await {
+                Ok(res) => res,
+                Err(err) => {
+                    error!("serving {}: {err}", req.uri.path.escape_debug());
+                    ResponseBuilder::new()
+                        .status_code(StatusCode::InternalServerError)
+                        .boxed()
+                }
+            }
+        })
+    }
+}
+
+async fn serve(req: &Request<'_>) -> io::Result<Response> {
+    let settings = req.settings();
+
+    if let Method::Custom(_) = req.method {
+        return Ok(ResponseBuilder::new()
+            .status_code(StatusCode::NotImplemented)
+            .boxed());
+    }
+    if !matches!(req.method, Method::Get | Method::Head) {
+        return Ok(ResponseBuilder::new()
+            .status_code(StatusCode::MethodNotAllowed)
+            .add_header("allow", "GET, HEAD")
+            .boxed());
+    }
+
+    let path = resolve(
+        &settings.content_root,
+        &req.uri.path,
+        settings.implicit_index,
+        settings.symlinks,
+    )
+    .await;
+
+    match path {
+        Ok(path) if path.is_dir() => Ok(serve_dir(settings, req, &path).await),
+        Ok(path) => serve_file(settings, req, &path).await,
+        Err(status) => {
+            debug!("rejecting path {}: {status}", req.uri.path.escape_debug());
+            Ok(ResponseBuilder::new().status_code(status).boxed())
+        }
+    }
+}
+
+async fn serve_dir(settings: &Settings, req: &Request<'_>, path: &Path) -> Response {
+    if !settings.directory_listing {
+        return ResponseBuilder::new()
+            .status_code(StatusCode::NotFound)
+            .boxed();
+    }
+
+    let rel = path.strip_prefix(&settings.content_root).unwrap_or(path);
+    let display = format!("/{}", rel.to_string_lossy());
+    let sort = Sort::from_query(
+        req.uri.query_param("sort").as_deref(),
+        req.uri.query_param("order").as_deref(),
+    );
+
+    let listing = match Listing::read(path, &display, sort).await {
+        Ok(v) => v,
+        Err(err) => {
+            error!("reading directory for listing: {}", err);
+            return ResponseBuilder::new()
+                .status_code(StatusCode::InternalServerError)
+                .boxed();
+        }
+    };
+
+    let accept = req.header.get("accept").map(|v| v.as_slice());
+    let (content_type, body) =
+        match preferred_media_type(accept, &["text/html", "application/json"]) {
+            "application/json" => ("application/json", listing.to_json()),
+            _ => ("text/html; charset=utf-8", listing.to_html(sort)),
+        };
+
+    let body = Text::from(body);
+    let len = body.len().unwrap_or_default();
+    ResponseBuilder::new()
+        .add_header("content-type", content_type)
+        .add_header("vary", "Accept")
+        .body(body, len)
+        .boxed()
+}
+
+async fn serve_file(settings: &Settings, req: &Request<'_>, path: &Path) -> io::Result<Response> {
+    debug!("trying to serve file {}", path.to_string_lossy());
+
+    let (mut f, meta) = match open_file(path).await {
+        Ok(v) => v,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Ok(ResponseBuilder::new()
+                .status_code(StatusCode::NotFound)
+                .boxed());
+        }
+        Err(err) => {
+            error!("opening file for response: {}", err);
+            return Ok(ResponseBuilder::new()
+                .status_code(StatusCode::InternalServerError)
+                .boxed());
+        }
+    };
+
+    let len = meta.len();
+    let mime = content_type(settings, path, &mut f).await?;
+    let mime = mime.as_deref();
+    let mut validators = Validators::new(path, &meta, settings.etag).await?;
+
+    let mut b = ResponseBuilder::new().add_header("accept-ranges", "bytes");
+
+    // Partial responses are always served from the identity representation
+    // because the size of an encoded stream is not known up front.
+    let accept_encoding = req.header.get("accept-encoding").map(|v| v.as_slice());
+    let sidecars = find_sidecars(path, settings.symlinks).await;
+    let compressible = settings.compression.applies(mime, len);
+    let mut encoding = None;
+    let mut precompressed = None;
+
+    if !sidecars.is_empty() || compressible {
+        b = b.add_header("vary", "Accept-Encoding");
+    }
+    if req.header.get("range").is_none() {
+        let available: Vec<_> = sidecars.iter().map(|(enc, _)| *enc).collect();
+        if let Some(enc) = Encoding::negotiate(accept_encoding, &available) {
+            let (_, sidecar) = sidecars.into_iter().find(|(e, _)| *e == enc).unwrap();
+            match open_file(&sidecar).await {
+                Ok((f, meta)) => {
+                    debug!("serving precompressed {}", sidecar.to_string_lossy());
+                    encoding = Some(enc);
+                    precompressed = Some((f, meta.len()));
+                }
+                Err(err) => debug!("opening sidecar {}: {err}", sidecar.to_string_lossy()),
+            }
+        }
+        // On-the-fly compression needs chunked encoding, which HTTP/1.0
+        // does not have.
+        if encoding.is_none() && compressible && req.version >= Version::Http11 {
+            encoding = Encoding::negotiate(accept_encoding, &Encoding::ALL);
+        }
+    }
+    if let Some(encoding) = encoding {
+        validators = validators.encoded(encoding.as_str());
+    }
+
+    b = b.add_header("etag", &validators.etag);
+
+    if let Some(last_modified) = validators.last_modified_header() {
+        b = b.add_header("last-modified", last_modified);
+    }
+
+    match validators.evaluate(&req.method, &req.header) {
+        Precondition::Proceed => {}
+        Precondition::NotModified => {
+            return Ok(b.status_code(StatusCode::NotModified).boxed());
+        }
+        Precondition::Failed => {
+            return Ok(b.status_code(StatusCode::PreconditionFailed).boxed());
+        }
+    }
+
+    let ranges = match req.header.get("range") {
+        Some(range) if req.method == Method::Get && validators.if_range(&req.header) => {
+            parse_range(&range.join(","), len)
+        }
+        _ => Ranges::Ignore,
+    };
+
+    let res = match ranges {
+        Ranges::Ignore => {
+            if let Some(mime) = mime {
+                b = b.add_header("content-type", mime);
+            }
+
+            match (encoding, precompressed) {
+                (Some(encoding), Some((f, size))) => b
+                    .add_header("content-encoding", encoding.as_str())
+                    .body(f, size as usize)
+                    .boxed(),
+                (Some(encoding), None) => b
+                    .add_header("content-encoding", encoding.as_str())
+                    .body_stream(encoding.encode(BufReader::new(f)))
+                    .boxed(),
+                (None, _) => b.body(f, len as usize).boxed(),
+            }
+        }
+        Ranges::Unsatisfiable => b
+            .status_code(StatusCode::RangeNotSatisfiable)
+            .add_header("content-range", format!("bytes */{len}"))
+            .boxed(),
+        Ranges::Satisfiable(ranges) if ranges.len() == 1 => {
+            let range = &ranges[0];
+            let size = range.end() - range.start() + 1;
+
+            let mut f = f;
+            f.seek(SeekFrom::Start(*range.start())).await?;
+
+            if let Some(mime) = mime {
+                b = b.add_header("content-type", mime);
+            }
+
+            b.status_code(StatusCode::PartialContent)
+                .add_header("content-range", content_range(range, len))
+                .body(f.take(size), size as usize)
+                .boxed()
+        }
+        Ranges::Satisfiable(ranges) => {
+            let boundary = multipart_boundary();
+            let (body, size) = multipart_byteranges(path, &ranges, len, mime, &boundary).await?;
+
+            b.status_code(StatusCode::PartialContent)
+                .add_header(
+                    "content-type",
+                    format!("multipart/byteranges; boundary={boundary}"),
+                )
+                .body(body, size)
+                .boxed()
+        }
+    };
+
+    Ok(res)
+}
+
+/// Determines the media type of the file at `path` from its extension, by
+/// sniffing its first bytes or from the configured default. Sniffing
+/// rewinds `f` to the start afterwards.
+async fn content_type(
+    settings: &Settings,
+    path: &Path,
+    f: &mut File,
+) -> io::Result<Option<String>> {
+    if let Some(ty) = settings.mime.lookup(path) {
+        return Ok(Some(ty.to_string()));
+    }
+
+    if settings.mime.sniff_enabled() {
+        let mut buf = Vec::with_capacity(SNIFF_LEN);
+        (&mut *f)
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut buf)
+            .await?;
+        f.seek(SeekFrom::Start(0)).await?;
+        if let Some(ty) = sniff(&buf) {
+            return Ok(Some(ty.to_string()));
+        }
+    }
+
+    Ok(settings.mime.default_type().map(String::from))
+}
+
+/// Looks for precompressed variants of `path` like `app.js.br` next to it.
+/// Unless symlinks are followed, only regular files are considered.
+async fn find_sidecars(path: &Path, symlinks: SymlinkPolicy) -> Vec<(Encoding, PathBuf)> {
+    let mut res = vec![];
+
+    for enc in Encoding::ALL {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(".");
+        sidecar.push(enc.extension());
+        let sidecar = PathBuf::from(sidecar);
+
+        let meta = match symlinks {
+            SymlinkPolicy::Follow => tokio::fs::metadata(&sidecar).await,
+            _ => tokio::fs::symlink_metadata(&sidecar).await,
+        };
+        if meta.is_ok_and(|m| m.is_file()) {
+            res.push((enc, sidecar));
+        }
+    }
+
+    res
+}
+
+/// Builds a `multipart/byteranges` body containing the given ranges of the
+/// file at `path`. Returns the body reader and its total size.
+async fn multipart_byteranges(
+    path: &Path,
+    ranges: &[RangeInclusive<u64>],
+    len: u64,
+    mime: Option<&str>,
+    boundary: &str,
+) -> io::Result<(Box<dyn AsyncRead + Send + Unpin>, usize)> {
+    let mut body: Box<dyn AsyncRead + Send + Unpin> = Box::new(NoOp);
+    let mut size = 0;
+
+    for range in ranges {
+        let mut part = format!("\r\n--{boundary}\r\n");
+        if let Some(mime) = mime {
+            part.push_str(&format!("Content-Type: {mime}\r\n"));
+        }
+        part.push_str(&format!(
+            "Content-Range: {}\r\n\r\n",
+            content_range(range, len)
+        ));
+
+        let part_len = range.end() - range.start() + 1;
+        let (mut f, _) = open_file(path).await?;
+        f.seek(SeekFrom::Start(*range.start())).await?;
+
+        size += part.len() + part_len as usize;
+        body = Box::new(body.chain(Text::from(part)).chain(f.take(part_len)));
+    }
+
+    let closing = format!("\r\n--{boundary}--\r\n");
+    size += closing.len();
+    body = Box::new(body.chain(Text::from(closing)));
+
+    Ok((body, size))
+}
+
+fn multipart_boundary() -> String {
+    // RandomState is seeded randomly, which is good enough to get a
+    // boundary that does not collide with the file contents.
+    format!("{:016x}", RandomState::new().build_hasher().finish())
+}
+
+async fn open_file(path: &Path) -> io::Result<(File, Metadata)> {
+    let f = File::open(path).await?;
+    let meta = f.metadata().await?;
+    Ok((f, meta))
+}
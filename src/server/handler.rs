@@ -0,0 +1,69 @@
+use super::{request::Request, response::ResponseBuilder};
+use std::{future::Future, pin::Pin, sync::Arc};
+use tokio::io::AsyncRead;
+
+/// Body of a response returned by a handler.
+pub type BoxBody = Pin<Box<dyn AsyncRead + Send>>;
+
+/// Response returned by a handler. Builders with any body are converted
+/// using `ResponseBuilder::boxed`.
+pub type Response = ResponseBuilder<BoxBody>;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Answers requests.
+///
+/// The connection sets the protocol version and persistence of the
+/// returned response and omits its body for `HEAD` requests. A handler may
+/// read the request body; whatever it leaves unread is discarded before the
+/// next request is read from the connection.
+pub trait Handler: Send + Sync + 'static {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>) -> BoxFuture<'a, Response>;
+}
+
+impl<H: Handler + ?Sized> Handler for Arc<H> {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>) -> BoxFuture<'a, Response> {
+        (**self).handle(req)
+    }
+}
+
+impl<H: Handler + ?Sized> Handler for Box<H> {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>) -> BoxFuture<'a, Response> {
+        (**self).handle(req)
+    }
+}
+
+/// Creates a handler from a closure returning a boxed future.
+///
+/// ```
+/// use http_server::{handler_fn, ResponseBuilder, StatusCode};
+///
+/// let hello = handler_fn(|req| {
+///     Box::pin(async move {
+///         let name = req.param("name").unwrap_or("world");
+///         ResponseBuilder::new()
+///             .add_header("content-type", "text/plain")
+///             .text(format!("Hello, {name}!"))
+///             .boxed()
+///     })
+/// });
+/// ```
+pub fn handler_fn<F>(f: F) -> HandlerFn<F>
+where
+    F: for<'a, 'c> Fn(&'a mut Request<'c>) -> BoxFuture<'a, Response> + Send + Sync + 'static,
+{
+    HandlerFn(f)
+}
+
+/// Handler created by `handler_fn`.
+#[derive(Clone, Copy)]
+pub struct HandlerFn<F>(F);
+
+impl<F> Handler for HandlerFn<F>
+where
+    F: for<'a, 'c> Fn(&'a mut Request<'c>) -> BoxFuture<'a, Response> + Send + Sync + 'static,
+{
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>) -> BoxFuture<'a, Response> {
+        (self.0)(req)
+    }
+}
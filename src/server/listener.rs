@@ -0,0 +1,498 @@
+use super::tls::Tls;
+use serde::Deserialize;
+use std::{
+    fmt,
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+#[cfg(unix)]
+use tokio::net::{unix::UCred, UnixListener, UnixStream};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+
+/// Accepts connections on a TCP socket or a Unix domain socket.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    /// Connections accepted by the inner listener over TLS.
+    Tls(Box<Listener>, Tls),
+}
+
+/// A connection accepted by a `Listener`, which is yet to perform the TLS
+/// handshake if `tls` is set.
+pub(crate) struct Accepted {
+    pub stream: Stream,
+    pub peer: Peer,
+    pub tls: Option<Tls>,
+}
+
+impl Listener {
+    /// Binds `addr`, which is either a socket address or the path of a Unix
+    /// domain socket prefixed with `unix:`. A Unix socket is created with
+    /// `options`, which are ignored for TCP.
+    pub async fn bind(addr: &str, options: &UnixSocketOptions) -> io::Result<Self> {
+        match addr.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => unix::bind(path.as_ref(), options).await.map(Self::Unix),
+            #[cfg(not(unix))]
+            Some(_) => {
+                let _ = options;
+                Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    "Unix domain sockets are not supported on this platform",
+                ))
+            }
+            None => TcpListener::bind(addr).await.map(Self::Tcp),
+        }
+    }
+
+    /// Takes the sockets passed by systemd socket activation along with
+    /// their names, which are `unknown` unless set in the socket unit. The
+    /// sockets are not passed on to child processes, and only the first
+    /// call returns them.
+    #[cfg(unix)]
+    pub fn inherited() -> io::Result<Vec<(String, Self)>> {
+        unix::inherited()
+    }
+
+    /// Serves the connections of this listener over TLS.
+    pub fn tls(self, tls: Tls) -> Self {
+        Self::Tls(Box::new(self), tls)
+    }
+
+    pub(crate) fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<Accepted>> {
+        let (stream, peer) = match self {
+            Self::Tcp(l) => {
+                let (stream, addr) = ready!(l.poll_accept(cx))?;
+                (Stream::Tcp(stream), Peer::Tcp(addr))
+            }
+            #[cfg(unix)]
+            Self::Unix(l) => {
+                let (stream, _) = ready!(l.poll_accept(cx))?;
+                let peer = Peer::Unix(stream.peer_cred()?);
+                (Stream::Unix(stream), peer)
+            }
+            Self::Tls(l, tls) => {
+                let accepted = ready!(l.poll_accept(cx))?;
+                return Poll::Ready(Ok(Accepted {
+                    tls: Some(tls.clone()),
+                    ..accepted
+                }));
+            }
+        };
+        Poll::Ready(Ok(Accepted {
+            stream,
+            peer,
+            tls: None,
+        }))
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(l: TcpListener) -> Self {
+        Self::Tcp(l)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for Listener {
+    fn from(l: UnixListener) -> Self {
+        Self::Unix(l)
+    }
+}
+
+/// Permissions of a Unix domain socket file. By default, they are
+/// determined by the umask and the user running the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct UnixSocketOptions {
+    /// File mode, e.g. `0o660`.
+    pub mode: Option<u32>,
+    /// User name or id owning the socket.
+    pub owner: Option<String>,
+    /// Group name or id owning the socket.
+    pub group: Option<String>,
+}
+
+/// The client of a connection.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum Peer {
+    /// A connection served with `Server::serve_connection` without a known
+    /// peer.
+    Unknown,
+    Tcp(SocketAddr),
+    /// The credentials of the process which connected to a Unix socket.
+    #[cfg(unix)]
+    Unix(UCred),
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "-"),
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            Self::Unix(cred) => {
+                write!(f, "uid={},gid={}", cred.uid(), cred.gid())?;
+                match cred.pid() {
+                    Some(pid) => write!(f, ",pid={pid}"),
+                    None => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+/// A connection accepted by a `Listener`.
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_read(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_write(cx, buf),
+            #[cfg(unix)]
+            Self::Unix(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_flush(cx),
+            #[cfg(unix)]
+            Self::Unix(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Tcp(s) => Pin::new(s).poll_shutdown(cx),
+            #[cfg(unix)]
+            Self::Unix(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(unix)]
+mod unix {
+    use super::*;
+    use std::{
+        env,
+        ffi::{c_char, CString},
+        fs::{self, Permissions},
+        mem::{self, MaybeUninit},
+        os::{
+            fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+            unix::fs::{chown, FileTypeExt, PermissionsExt},
+        },
+        path::Path,
+        process, ptr,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use tracing::info;
+
+    /// The first file descriptor passed by systemd, see `sd_listen_fds(3)`.
+    const LISTEN_FDS_START: RawFd = 3;
+
+    /// Whether the sockets passed by systemd have been taken already.
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+
+    pub fn inherited() -> io::Result<Vec<(String, Listener)>> {
+        // The environment is left alone, as changing it is unsound while
+        // other threads may be running. Child processes see a `LISTEN_PID`
+        // not matching their own and ignore the variables.
+        if TAKEN.swap(true, Ordering::SeqCst) {
+            return Ok(vec![]);
+        }
+        let pid = env::var("LISTEN_PID").ok();
+        let fds = env::var("LISTEN_FDS").ok();
+        let names = env::var("LISTEN_FDNAMES").ok();
+
+        // The sockets are meant for another process if the pid does not
+        // match, e.g. when inherited from a parent which was activated.
+        let (Some(pid), Some(fds)) = (pid, fds) else {
+            return Ok(vec![]);
+        };
+        if pid.parse() != Ok(process::id()) {
+            return Ok(vec![]);
+        }
+        let fds: RawFd = fds
+            .parse()
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "invalid LISTEN_FDS"))?;
+        let names: Vec<_> = names.as_deref().unwrap_or_default().split(':').collect();
+
+        // Every descriptor is checked before any is taken, so an invalid
+        // one doesn't leave the others half adopted.
+        let checked = (0..fds)
+            .map(|i| {
+                let fd = LISTEN_FDS_START + i;
+                let name = names.get(i as usize).copied().unwrap_or_default();
+                let name = if name.is_empty() { "unknown" } else { name };
+                Ok((name.to_string(), fd, check(fd)?))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let owned: Vec<_> = checked
+            .into_iter()
+            .map(|(name, fd, family)| {
+                // SAFETY: systemd passes ownership of these descriptors to
+                // this process and nothing else has taken them, as `TAKEN`
+                // was not set. `check` made sure they are open.
+                (name, unsafe { OwnedFd::from_raw_fd(fd) }, family)
+            })
+            .collect();
+
+        owned
+            .into_iter()
+            .map(|(name, fd, family)| Ok((name, adopt(fd, family)?)))
+            .collect()
+    }
+
+    /// Checks that `fd` is a listening stream socket and returns its
+    /// address family.
+    fn check(fd: RawFd) -> io::Result<libc::c_int> {
+        let invalid = |what: &str| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("inherited file descriptor {fd} is not a {what}"),
+            )
+        };
+
+        // SAFETY: `addr` and `len` describe a buffer large enough for any
+        // socket address. An invalid descriptor makes the call fail.
+        let family = unsafe {
+            let mut addr = MaybeUninit::<libc::sockaddr_storage>::zeroed();
+            let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            if libc::getsockname(fd, addr.as_mut_ptr().cast(), &mut len) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            addr.assume_init().ss_family as libc::c_int
+        };
+        if !matches!(family, libc::AF_INET | libc::AF_INET6 | libc::AF_UNIX) {
+            return Err(invalid("TCP or Unix socket"));
+        }
+        if socket_option(fd, libc::SO_TYPE)? != libc::SOCK_STREAM {
+            return Err(invalid("stream socket"));
+        }
+        if socket_option(fd, libc::SO_ACCEPTCONN)? == 0 {
+            return Err(invalid("listening socket"));
+        }
+        Ok(family)
+    }
+
+    fn socket_option(fd: RawFd, option: libc::c_int) -> io::Result<libc::c_int> {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: `value` and `len` describe a buffer of the size the
+        // integer options read here have.
+        let res = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                option,
+                (&mut value as *mut libc::c_int).cast(),
+                &mut len,
+            )
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(value)
+    }
+
+    fn adopt(fd: OwnedFd, family: libc::c_int) -> io::Result<Listener> {
+        // SAFETY: `fd` is a valid descriptor owned by this process.
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if family == libc::AF_UNIX {
+            let l = std::os::unix::net::UnixListener::from(fd);
+            l.set_nonblocking(true)?;
+            UnixListener::from_std(l).map(Listener::Unix)
+        } else {
+            let l = std::net::TcpListener::from(fd);
+            l.set_nonblocking(true)?;
+            TcpListener::from_std(l).map(Listener::Tcp)
+        }
+    }
+
+    pub async fn bind(path: &Path, options: &UnixSocketOptions) -> io::Result<UnixListener> {
+        remove_stale(path).await?;
+
+        let listener = UnixListener::bind(path)?;
+        if let Some(mode) = options.mode {
+            fs::set_permissions(path, Permissions::from_mode(mode))?;
+        }
+        if options.owner.is_some() || options.group.is_some() {
+            let uid = options.owner.as_deref().map(user_id).transpose()?;
+            let gid = options.group.as_deref().map(group_id).transpose()?;
+            chown(path, uid, gid)?;
+        }
+
+        Ok(listener)
+    }
+
+    /// Removes a socket file left behind by a server which did not shut
+    /// down cleanly. Sockets still accepting connections and other files are
+    /// left alone.
+    async fn remove_stale(path: &Path) -> io::Result<()> {
+        let meta = match fs::symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+
+        match UnixStream::connect(path).await {
+            Ok(_) => Err(io::Error::new(
+                ErrorKind::AddrInUse,
+                format!("{} is in use", path.display()),
+            )),
+            Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+                info!("Removing stale socket {}", path.display());
+                fs::remove_file(path)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    const LOOKUP_BUF_SIZE: usize = 16 * 1024;
+
+    fn user_id(user: &str) -> io::Result<u32> {
+        if let Ok(id) = user.parse() {
+            return Ok(id);
+        }
+
+        let name = CString::new(user)?;
+        let mut pwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut buf = vec![0 as c_char; LOOKUP_BUF_SIZE];
+        let mut res = ptr::null_mut();
+        // SAFETY: All pointers are valid for the duration of the call and
+        // `buf.len()` is the size of `buf`.
+        let rc = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut res,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::from_raw_os_error(rc));
+        }
+        if res.is_null() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("unknown user {user}"),
+            ));
+        }
+        // SAFETY: `res` points to `pwd`, which was initialized by the call.
+        Ok(unsafe { (*res).pw_uid })
+    }
+
+    fn group_id(group: &str) -> io::Result<u32> {
+        if let Ok(id) = group.parse() {
+            return Ok(id);
+        }
+
+        let name = CString::new(group)?;
+        let mut grp = MaybeUninit::<libc::group>::uninit();
+        let mut buf = vec![0 as c_char; LOOKUP_BUF_SIZE];
+        let mut res = ptr::null_mut();
+        // SAFETY: All pointers are valid for the duration of the call and
+        // `buf.len()` is the size of `buf`.
+        let rc = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                grp.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut res,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::from_raw_os_error(rc));
+        }
+        if res.is_null() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("unknown group {group}"),
+            ));
+        }
+        // SAFETY: `res` points to `grp`, which was initialized by the call.
+        Ok(unsafe { (*res).gr_gid })
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn lookup_ids() {
+            assert_eq!(user_id("root").unwrap(), 0);
+            assert_eq!(user_id("1234").unwrap(), 1234);
+            assert_eq!(group_id("0").unwrap(), 0);
+            assert_eq!(
+                user_id("no-such-user-exists").unwrap_err().kind(),
+                ErrorKind::NotFound
+            );
+        }
+
+        #[test]
+        fn check_inherited() {
+            let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            assert_eq!(check(tcp.as_raw_fd()).unwrap(), libc::AF_INET);
+
+            let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+            assert_eq!(
+                check(udp.as_raw_fd()).unwrap_err().kind(),
+                ErrorKind::InvalidInput
+            );
+
+            let stream = std::net::TcpStream::connect(tcp.local_addr().unwrap()).unwrap();
+            assert_eq!(
+                check(stream.as_raw_fd()).unwrap_err().kind(),
+                ErrorKind::InvalidInput
+            );
+
+            let file = fs::File::open("Cargo.toml").unwrap();
+            assert!(check(file.as_raw_fd()).is_err());
+        }
+    }
+}
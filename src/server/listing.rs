@@ -0,0 +1,207 @@
+use super::uri::percent_encode;
+use serde::Serialize;
+use std::{
+    fmt::Write,
+    io,
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::fs;
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch.
+    #[serde(rename = "mtime")]
+    pub modified: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Size => "size",
+            Self::Modified => "mtime",
+        }
+    }
+}
+
+/// Sort order of a listing, taken from the `sort` and `order` query
+/// parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct Sort {
+    pub key: SortKey,
+    pub desc: bool,
+}
+
+impl Sort {
+    pub fn from_query(sort: Option<&str>, order: Option<&str>) -> Self {
+        let key = match sort {
+            Some("size") => SortKey::Size,
+            Some("mtime") => SortKey::Modified,
+            _ => SortKey::Name,
+        };
+        Self {
+            key,
+            desc: order == Some("desc"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Listing {
+    pub path: String,
+    pub entries: Vec<Entry>,
+}
+
+impl Listing {
+    /// Reads the entries of `dir`. `path` is the decoded request path which
+    /// is shown as the title of the listing.
+    pub async fn read(dir: &Path, path: &str, sort: Sort) -> io::Result<Self> {
+        let mut entries = vec![];
+
+        let mut rd = fs::read_dir(dir).await?;
+        while let Some(entry) = rd.next_entry().await? {
+            // Follows symlinks, so links are listed like their targets.
+            let Ok(meta) = fs::metadata(entry.path()).await else {
+                continue;
+            };
+            entries.push(Entry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                kind: if meta.is_dir() {
+                    EntryKind::Directory
+                } else {
+                    EntryKind::File
+                },
+                size: if meta.is_dir() { 0 } else { meta.len() },
+                modified: meta
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+            });
+        }
+
+        // Directories are always listed first.
+        entries.sort_by(|a, b| {
+            let ord = match sort.key {
+                SortKey::Name => a.name.cmp(&b.name),
+                SortKey::Size => a.size.cmp(&b.size),
+                SortKey::Modified => a.modified.cmp(&b.modified),
+            }
+            .then_with(|| a.name.cmp(&b.name));
+            let ord = if sort.desc { ord.reverse() } else { ord };
+            (b.kind == EntryKind::Directory)
+                .cmp(&(a.kind == EntryKind::Directory))
+                .then(ord)
+        });
+
+        Ok(Self {
+            path: path.into(),
+            entries,
+        })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("listing is always serializable")
+    }
+
+    /// Renders the listing as HTML page. All links are absolute, so `/dir`
+    /// and `/dir/` render the same page.
+    pub fn to_html(&self, sort: Sort) -> String {
+        let title = html_escape(&self.path);
+        let base: String = self
+            .path
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| format!("/{}", percent_encode(s)))
+            .collect();
+
+        let mut res = String::new();
+        let _ = write!(
+            res,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>Index of {title}</title>\n</head>\n<body>\n\
+             <h1>Index of {title}</h1>\n<table>\n<tr>"
+        );
+
+        for (key, label) in [
+            (SortKey::Name, "Name"),
+            (SortKey::Size, "Size"),
+            (SortKey::Modified, "Last modified"),
+        ] {
+            let order = if sort.key == key && !sort.desc {
+                "desc"
+            } else {
+                "asc"
+            };
+            let _ = write!(
+                res,
+                "<th><a href=\"?sort={}&amp;order={order}\">{label}</a></th>",
+                key.as_str()
+            );
+        }
+        res.push_str("</tr>\n");
+
+        if !base.is_empty() {
+            let parent = &base[..base.rfind('/').unwrap_or(0)];
+            let _ = writeln!(
+                res,
+                "<tr><td><a href=\"{parent}/\">../</a></td><td></td><td></td></tr>"
+            );
+        }
+
+        for entry in &self.entries {
+            let (slash, size) = match entry.kind {
+                EntryKind::Directory => ("/", "-".to_string()),
+                EntryKind::File => ("", entry.size.to_string()),
+            };
+            let modified = entry
+                .modified
+                .map(|s| httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(s)))
+                .unwrap_or_default();
+            let _ = writeln!(
+                res,
+                "<tr><td><a href=\"{base}/{href}{slash}\">{name}{slash}</a></td>\
+                 <td>{size}</td><td>{modified}</td></tr>",
+                href = percent_encode(&entry.name),
+                name = html_escape(&entry.name),
+            );
+        }
+
+        res.push_str("</table>\n</body>\n</html>\n");
+        res
+    }
+}
+
+fn html_escape(v: &str) -> String {
+    let mut res = String::with_capacity(v.len());
+    for c in v.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
@@ -0,0 +1,445 @@
+use super::{
+    handler::{BoxFuture, Handler, Response},
+    request::Request,
+    resolve::normalize,
+    response::ResponseBuilder,
+    statuscode::StatusCode,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    sync::Arc,
+};
+use tokio::time::Instant;
+use tracing::info;
+
+/// Wraps request handling to inspect or modify requests and responses.
+///
+/// A middleware either passes the request on using `next`, which runs the
+/// remaining middleware and the handler, or answers it on its own.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>, next: Next<'a>) -> BoxFuture<'a, Response>;
+}
+
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>, next: Next<'a>) -> BoxFuture<'a, Response> {
+        (**self).handle(req, next)
+    }
+}
+
+/// The rest of the chain following a middleware.
+pub struct Next<'a> {
+    layers: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl<'a> Next<'a> {
+    /// Passes the request to the next middleware, or to the handler at the
+    /// end of the chain.
+    pub fn run(self, req: &'a mut Request<'_>) -> BoxFuture<'a, Response> {
+        match self.layers.split_first() {
+            Some((layer, layers)) => layer.handle(
+                req,
+                Next {
+                    layers,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.handle(req),
+        }
+    }
+}
+
+/// Creates a middleware from a closure returning a boxed future.
+pub fn middleware_fn<F>(f: F) -> MiddlewareFn<F>
+where
+    F: for<'a, 'c> Fn(&'a mut Request<'c>, Next<'a>) -> BoxFuture<'a, Response>
+        + Send
+        + Sync
+        + 'static,
+{
+    MiddlewareFn(f)
+}
+
+/// Middleware created by `middleware_fn`.
+#[derive(Clone, Copy)]
+pub struct MiddlewareFn<F>(F);
+
+impl<F> Middleware for MiddlewareFn<F>
+where
+    F: for<'a, 'c> Fn(&'a mut Request<'c>, Next<'a>) -> BoxFuture<'a, Response>
+        + Send
+        + Sync
+        + 'static,
+{
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>, next: Next<'a>) -> BoxFuture<'a, Response> {
+        (self.0)(req, next)
+    }
+}
+
+/// A handler wrapped into middleware. The first layer sees the request
+/// first and the response last.
+pub(crate) struct Chain {
+    pub layers: Vec<Arc<dyn Middleware>>,
+    pub handler: Arc<dyn Handler>,
+}
+
+impl Handler for Chain {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>) -> BoxFuture<'a, Response> {
+        Next {
+            layers: &self.layers,
+            handler: &*self.handler,
+        }
+        .run(req)
+    }
+}
+
+/// Middleware configured in the `[[middleware]]` tables of the config
+/// file, which are applied in the order they are listed.
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum MiddlewareConfig {
+    AccessLog,
+    Headers {
+        set: HashMap<String, String>,
+    },
+    BasicAuth {
+        #[serde(default = "default_realm")]
+        realm: String,
+        users: HashMap<String, String>,
+        #[serde(default)]
+        paths: Vec<String>,
+    },
+    ClientCert {
+        #[serde(default)]
+        paths: Vec<String>,
+    },
+}
+
+/// Hides the passwords of `BasicAuth`, which would end up in the log
+/// otherwise.
+impl fmt::Debug for MiddlewareConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AccessLog => f.write_str("AccessLog"),
+            Self::Headers { set } => f.debug_struct("Headers").field("set", set).finish(),
+            Self::BasicAuth {
+                realm,
+                users,
+                paths,
+            } => {
+                let users: BTreeMap<_, _> = users.keys().map(|u| (u, Redacted)).collect();
+                f.debug_struct("BasicAuth")
+                    .field("realm", realm)
+                    .field("users", &users)
+                    .field("paths", paths)
+                    .finish()
+            }
+            Self::ClientCert { paths } => {
+                f.debug_struct("ClientCert").field("paths", paths).finish()
+            }
+        }
+    }
+}
+
+struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+fn default_realm() -> String {
+    "Restricted".into()
+}
+
+impl MiddlewareConfig {
+    pub fn build(&self) -> Arc<dyn Middleware> {
+        match self {
+            Self::AccessLog => Arc::new(AccessLog),
+            Self::Headers { set } => Arc::new(SetHeaders::new(set.clone())),
+            Self::BasicAuth {
+                realm,
+                users,
+                paths,
+            } => Arc::new(BasicAuth::new(realm, users.clone()).paths(paths.clone())),
+            Self::ClientCert { paths } => Arc::new(RequireClientCert::new().paths(paths.clone())),
+        }
+    }
+}
+
+/// Logs every request with its peer, the subject of the client certificate,
+/// the response status and the time it took to produce the response.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AccessLog;
+
+impl Middleware for AccessLog {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let start = Instant::now();
+            let peer = req.peer().clone();
+            let client = match req.client_cert() {
+                Some(cert) => format!("\"{}\"", cert.subject),
+                None => "-".into(),
+            };
+            let line = format!("{} {} {}", req.method, req.uri, req.version);
+            let res = next.run(req).await;
+            let status = res.status().code();
+            info!("{peer} {client} \"{line}\" {status} {:?}", start.elapsed());
+            res
+        })
+    }
+}
+
+/// Sets response headers, replacing values set by the handler.
+#[derive(Debug, Clone, Default)]
+pub struct SetHeaders {
+    headers: Vec<(String, String)>,
+}
+
+impl SetHeaders {
+    pub fn new<I, K, V>(headers: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        Self {
+            headers: headers
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+        }
+    }
+}
+
+impl Middleware for SetHeaders {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let mut res = next.run(req).await;
+            for (k, v) in &self.headers {
+                res = res.set_header(k, v);
+            }
+            res
+        })
+    }
+}
+
+/// Requires HTTP Basic authentication as specified in RFC 7617. Requests
+/// without valid credentials are answered with `401 Unauthorized`.
+#[derive(Debug, Clone)]
+pub struct BasicAuth {
+    realm: String,
+    users: HashMap<String, [u8; 32]>,
+    paths: Vec<String>,
+}
+
+impl BasicAuth {
+    /// Creates the middleware accepting the given user names and
+    /// passwords.
+    pub fn new<I, U, P>(realm: &str, users: I) -> Self
+    where
+        I: IntoIterator<Item = (U, P)>,
+        U: Into<String>,
+        P: AsRef<[u8]>,
+    {
+        Self {
+            realm: realm.into(),
+            users: users
+                .into_iter()
+                .map(|(u, p)| (u.into(), Sha256::digest(p).into()))
+                .collect(),
+            paths: vec![],
+        }
+    }
+
+    /// Restricts authentication to requests whose path starts with one of
+    /// the given prefixes. By default, every request is authenticated.
+    pub fn paths<I, S>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Returns `None` if `path` can't be normalized.
+    fn applies(&self, path: &str) -> Option<bool> {
+        normalize(path)?;
+        Some(matches_paths(&self.paths, path))
+    }
+
+    fn authorized(&self, req: &Request<'_>) -> bool {
+        let Some(values) = req.header.get("authorization") else {
+            return false;
+        };
+        values.iter().any(|v| {
+            let Some((scheme, credentials)) = v.trim().split_once(' ') else {
+                return false;
+            };
+            if !scheme.eq_ignore_ascii_case("basic") {
+                return false;
+            }
+            let Ok(decoded) = STANDARD.decode(credentials.trim()) else {
+                return false;
+            };
+            let Some((user, password)) = String::from_utf8_lossy(&decoded)
+                .split_once(':')
+                .map(|(u, p)| (u.to_string(), p.to_string()))
+            else {
+                return false;
+            };
+
+            // Comparing digests keeps the time taken independent of how
+            // much of the password matches.
+            let digest: [u8; 32] = Sha256::digest(password).into();
+            self.users.get(&user).is_some_and(|d| *d == digest)
+        })
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let Some(applies) = self.applies(&req.uri.path) else {
+                return ResponseBuilder::new()
+                    .status_code(StatusCode::BadRequest)
+                    .boxed();
+            };
+            if !applies || self.authorized(req) {
+                return next.run(req).await;
+            }
+
+            ResponseBuilder::new()
+                .status_code(StatusCode::Unauthorized)
+                .add_header(
+                    "www-authenticate",
+                    format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm),
+                )
+                .boxed()
+        })
+    }
+}
+
+/// Requires a verified TLS client certificate, see `Tls::client_auth`.
+/// Requests without one are answered with `403 Forbidden`.
+#[derive(Debug, Clone, Default)]
+pub struct RequireClientCert {
+    paths: Vec<String>,
+}
+
+impl RequireClientCert {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restricts the requirement to requests whose path starts with one of
+    /// the given prefixes. By default, it applies to every request.
+    pub fn paths<I, S>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.paths = paths.into_iter().map(Into::into).collect();
+        self
+    }
+}
+
+impl Middleware for RequireClientCert {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>, next: Next<'a>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            if normalize(&req.uri.path).is_none() {
+                return ResponseBuilder::new()
+                    .status_code(StatusCode::BadRequest)
+                    .boxed();
+            }
+            if req.client_cert().is_some() || !matches_paths(&self.paths, &req.uri.path) {
+                return next.run(req).await;
+            }
+            ResponseBuilder::new()
+                .status_code(StatusCode::Forbidden)
+                .boxed()
+        })
+    }
+}
+
+/// Whether `path` is one of the prefixes in `paths`, or below one of them.
+/// An empty list matches every path. Paths are normalized the way the file
+/// server does, so `//admin` and `/pub/../admin` match `/admin`. Paths
+/// which can't be normalized match as well.
+fn matches_paths(paths: &[String], path: &str) -> bool {
+    let Some(path) = normalize(path) else {
+        return true;
+    };
+    paths.is_empty()
+        || paths
+            .iter()
+            .any(|p| normalize(p).is_some_and(|p| path.starts_with(p)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn middleware_config() {
+        let cfg: HashMap<String, Vec<MiddlewareConfig>> = toml::from_str(
+            r#"
+            [[middleware]]
+            type = "access-log"
+
+            [[middleware]]
+            type = "basic-auth"
+            users = { admin = "secret" }
+            paths = ["/admin"]
+
+            [[middleware]]
+            type = "client-cert"
+            paths = ["/internal"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            cfg["middleware"],
+            [
+                MiddlewareConfig::AccessLog,
+                MiddlewareConfig::BasicAuth {
+                    realm: "Restricted".into(),
+                    users: HashMap::from([("admin".into(), "secret".into())]),
+                    paths: vec!["/admin".into()],
+                },
+                MiddlewareConfig::ClientCert {
+                    paths: vec!["/internal".into()],
+                },
+            ]
+        );
+        let debug = format!("{:?}", cfg["middleware"][1]);
+        assert!(debug.contains("\"admin\": <redacted>"), "{debug}");
+        assert!(!debug.contains("secret"), "{debug}");
+    }
+
+    #[test]
+    fn auth_paths() {
+        let auth = BasicAuth::new("test", [("a", "b")]).paths(["/admin/"]);
+        assert_eq!(auth.applies("/admin"), Some(true));
+        assert_eq!(auth.applies("/admin/users"), Some(true));
+        assert_eq!(auth.applies("//admin/users"), Some(true));
+        assert_eq!(auth.applies("/./admin/users"), Some(true));
+        assert_eq!(auth.applies("/pub/../admin"), Some(true));
+        assert_eq!(auth.applies("/administrator"), Some(false));
+        assert_eq!(auth.applies("/"), Some(false));
+        assert_eq!(auth.applies("/../admin"), None);
+
+        assert_eq!(
+            BasicAuth::new("test", [("a", "b")]).applies("/"),
+            Some(true)
+        );
+    }
+}
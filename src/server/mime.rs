@@ -0,0 +1,345 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Number of bytes read from the start of a file for content sniffing.
+pub const SNIFF_LEN: usize = 512;
+
+/// Built-in mapping of file extensions to media types.
+const BUILTIN: &[(&str, &str)] = &[
+    // Text
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("txt", "text/plain"),
+    ("text", "text/plain"),
+    ("log", "text/plain"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("tsv", "text/tab-separated-values"),
+    ("md", "text/markdown"),
+    ("markdown", "text/markdown"),
+    ("ics", "text/calendar"),
+    ("vcf", "text/vcard"),
+    ("vtt", "text/vtt"),
+    ("xml", "application/xml"),
+    ("xsl", "application/xml"),
+    ("xhtml", "application/xhtml+xml"),
+    ("rss", "application/rss+xml"),
+    ("atom", "application/atom+xml"),
+    // Scripts and data
+    ("js", "application/javascript; charset=utf-8"),
+    ("mjs", "application/javascript; charset=utf-8"),
+    ("cjs", "application/javascript; charset=utf-8"),
+    ("map", "application/json"),
+    ("json", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("webmanifest", "application/manifest+json"),
+    ("wasm", "application/wasm"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("toml", "application/toml"),
+    ("sh", "application/x-sh"),
+    // Images
+    ("png", "image/png"),
+    ("apng", "image/apng"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("jfif", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("jxl", "image/jxl"),
+    ("bmp", "image/bmp"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("cur", "image/x-icon"),
+    ("svg", "image/svg+xml"),
+    ("svgz", "image/svg+xml"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    // Fonts
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    // Audio
+    ("mp3", "audio/mpeg"),
+    ("m4a", "audio/mp4"),
+    ("aac", "audio/aac"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/ogg"),
+    ("wav", "audio/wav"),
+    ("weba", "audio/webm"),
+    ("flac", "audio/flac"),
+    ("mid", "audio/midi"),
+    ("midi", "audio/midi"),
+    // Video
+    ("mp4", "video/mp4"),
+    ("m4v", "video/mp4"),
+    ("webm", "video/webm"),
+    ("ogv", "video/ogg"),
+    ("mov", "video/quicktime"),
+    ("avi", "video/x-msvideo"),
+    ("mkv", "video/x-matroska"),
+    ("mpeg", "video/mpeg"),
+    ("mpg", "video/mpeg"),
+    ("ts", "video/mp2t"),
+    ("m3u8", "application/vnd.apple.mpegurl"),
+    ("mpd", "application/dash+xml"),
+    // Documents
+    ("pdf", "application/pdf"),
+    ("rtf", "application/rtf"),
+    ("epub", "application/epub+zip"),
+    ("doc", "application/msword"),
+    (
+        "docx",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("xls", "application/vnd.ms-excel"),
+    (
+        "xlsx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    (
+        "pptx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    // Archives and binaries
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tgz", "application/gzip"),
+    ("bz2", "application/x-bzip2"),
+    ("xz", "application/x-xz"),
+    ("zst", "application/zstd"),
+    ("br", "application/x-brotli"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("jar", "application/java-archive"),
+    ("apk", "application/vnd.android.package-archive"),
+    ("deb", "application/vnd.debian.binary-package"),
+    ("rpm", "application/x-rpm"),
+    ("dmg", "application/x-apple-diskimage"),
+    ("iso", "application/x-iso9660-image"),
+    ("exe", "application/vnd.microsoft.portable-executable"),
+    ("bin", "application/octet-stream"),
+];
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct MimeConfig {
+    /// Files in the `mime.types` format to load, like `/etc/mime.types`.
+    pub types_files: Vec<PathBuf>,
+    /// Type of files with an unknown extension. If unset, no
+    /// `Content-Type` is sent for them.
+    pub default: Option<String>,
+    /// Guess the type of files with an unknown extension from their first
+    /// bytes before falling back to `default`.
+    pub sniff: bool,
+    /// Extension to type overrides, taking precedence over everything else.
+    pub types: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MimeTypes {
+    types: HashMap<String, String>,
+    default: Option<String>,
+    sniff: bool,
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        Self {
+            types: BUILTIN
+                .iter()
+                .map(|(ext, ty)| (ext.to_string(), ty.to_string()))
+                .collect(),
+            default: None,
+            sniff: false,
+        }
+    }
+}
+
+impl MimeTypes {
+    /// Builds the table from the built-in types, the configured type files
+    /// and the overrides, in increasing order of precedence.
+    pub fn from_config(cfg: &MimeConfig) -> io::Result<Self> {
+        let mut res = Self::default();
+
+        for file in &cfg.types_files {
+            res.load_mime_types(&fs::read_to_string(file)?);
+        }
+        for (ext, ty) in &cfg.types {
+            res.insert(ext, ty);
+        }
+
+        res.default = cfg.default.clone();
+        res.sniff = cfg.sniff;
+
+        Ok(res)
+    }
+
+    pub fn insert(&mut self, ext: &str, ty: &str) {
+        self.types
+            .insert(ext.trim_start_matches('.').to_lowercase(), ty.to_string());
+    }
+
+    /// Adds the types of a file in the `mime.types` format, where each line
+    /// holds a type followed by its extensions.
+    pub fn load_mime_types(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let Some(ty) = fields.next() else {
+                continue;
+            };
+            for ext in fields {
+                self.insert(ext, ty);
+            }
+        }
+    }
+
+    /// Returns the type registered for the extension of `path`.
+    pub fn lookup(&self, path: &Path) -> Option<&str> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        self.types.get(&ext).map(String::as_str)
+    }
+
+    pub fn sniff_enabled(&self) -> bool {
+        self.sniff
+    }
+
+    pub fn default_type(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+}
+
+/// Guesses a media type from the first bytes of a file.
+pub fn sniff(buf: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"(\xb5/\xfd", "application/zstd"),
+        (b"\0asm", "application/wasm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"OggS", "audio/ogg"),
+        (b"ID3", "audio/mpeg"),
+        (b"fLaC", "audio/flac"),
+        (b"\x1aE\xdf\xa3", "video/webm"),
+        (b"\0\0\x01\0", "image/vnd.microsoft.icon"),
+        (b"BM", "image/bmp"),
+    ];
+
+    if let Some((_, ty)) = SIGNATURES.iter().find(|(sig, _)| buf.starts_with(sig)) {
+        return Some(ty);
+    }
+
+    if buf.len() >= 12 && &buf[..4] == b"RIFF" {
+        match &buf[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            b"AVI " => return Some("video/x-msvideo"),
+            _ => {}
+        }
+    }
+    if buf.len() >= 12 && &buf[4..8] == b"ftyp" {
+        return match &buf[8..12] {
+            b"avif" => Some("image/avif"),
+            b"M4A " => Some("audio/mp4"),
+            b"qt  " => Some("video/quicktime"),
+            _ => Some("video/mp4"),
+        };
+    }
+
+    let text = buf.trim_ascii_start();
+    let starts_with_ci = |prefix: &[u8]| {
+        text.len() >= prefix.len() && text[..prefix.len()].eq_ignore_ascii_case(prefix)
+    };
+    if starts_with_ci(b"<!doctype html") || starts_with_ci(b"<html") {
+        return Some("text/html; charset=utf-8");
+    }
+    if starts_with_ci(b"<svg") {
+        return Some("image/svg+xml");
+    }
+    if starts_with_ci(b"<?xml") {
+        return Some("application/xml");
+    }
+
+    if is_text(buf) {
+        return Some("text/plain; charset=utf-8");
+    }
+
+    None
+}
+
+/// Checks if `buf` looks like UTF-8 text. A multi-byte character may have
+/// been cut off at the end of the buffer.
+fn is_text(buf: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(buf) {
+        Ok(_) => true,
+        Err(err) => err.error_len().is_none() && buf.len() - err.valid_up_to() < 4,
+    };
+    valid
+        && !buf
+            .iter()
+            .any(|b| b.is_ascii_control() && !b"\t\n\r\x0c".contains(b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup() {
+        let mut cfg = MimeConfig::default();
+        cfg.types.insert("md".into(), "text/x-markdown".into());
+        cfg.types.insert(".foo".into(), "application/x-foo".into());
+        let mut types = MimeTypes::from_config(&cfg).unwrap();
+        types.load_mime_types("# comment\napplication/x-bar\tbar baz\n\ntext/css css # x\n");
+
+        assert_eq!(types.lookup(Path::new("a.SVG")), Some("image/svg+xml"));
+        assert_eq!(types.lookup(Path::new("a/b.woff2")), Some("font/woff2"));
+        assert_eq!(types.lookup(Path::new("a.md")), Some("text/x-markdown"));
+        assert_eq!(types.lookup(Path::new("a.foo")), Some("application/x-foo"));
+        assert_eq!(types.lookup(Path::new("a.baz")), Some("application/x-bar"));
+        assert_eq!(types.lookup(Path::new("a.css")), Some("text/css"));
+        assert_eq!(types.lookup(Path::new("Makefile")), None);
+    }
+
+    #[test]
+    fn sniffing() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0"), Some("image/png"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"\0\0\0\x18ftypmp42"), Some("video/mp4"));
+        assert_eq!(
+            sniff(b"\n  <!DOCTYPE html><html>"),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            sniff("plain text ü".as_bytes()),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            sniff(&"ü".as_bytes()[..1]),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(sniff(b"\0\x01\x02binary"), None);
+    }
+}
@@ -1,49 +1,410 @@
+mod body;
+mod compress;
+mod conditional;
 mod conn;
+mod error;
+mod files;
+mod handler;
+mod listener;
+mod listing;
+mod middleware;
+mod mime;
+mod negotiate;
+mod parser;
+mod range;
 mod readers;
 mod request;
+mod resolve;
 mod response;
+mod router;
 mod statuscode;
+mod timeout;
+mod tls;
+mod uri;
 
+pub use body::Body;
+pub use compress::Compression;
+pub use conditional::EtagMode;
+pub use error::RequestError;
+pub use files::FileServer;
+pub use handler::{handler_fn, BoxBody, BoxFuture, Handler, HandlerFn, Response};
+pub use listener::{Listener, Peer, UnixSocketOptions};
+pub use middleware::{
+    middleware_fn, AccessLog, BasicAuth, Middleware, MiddlewareConfig, MiddlewareFn, Next,
+    RequireClientCert, SetHeaders,
+};
+pub use mime::{MimeConfig, MimeTypes};
+pub use parser::Limits;
+pub use readers::{ContentLength, NoOp, Text};
+pub use request::{HeaderMap, Method, Request, Version};
+pub use resolve::SymlinkPolicy;
+pub use response::ResponseBuilder;
+pub use router::Router;
+pub use statuscode::StatusCode;
+pub use timeout::Timeouts;
+pub use tls::{
+    Certificates, ClientAuth, ClientAuthConfig, ClientAuthMode, ClientCert, HttpsRedirect,
+    SniConfig, Tls, TlsConfig,
+};
+pub use uri::{Form, Uri};
+
+use arc_swap::ArcSwap;
 use conn::Conn;
-use std::path::PathBuf;
-use tokio::net::TcpListener;
-use tracing::{debug, error};
+use listener::Accepted;
+use middleware::Chain;
+use std::{
+    future::{poll_fn, Future},
+    io,
+    path::PathBuf,
+    sync::Arc,
+    task::Poll,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::watch,
+    task::JoinSet,
+    time::timeout,
+};
+use tracing::{debug, error, info, warn};
 
-pub struct Server {
-    listener: TcpListener,
-    content_root: PathBuf,
-    implicit_index: bool,
+/// The settings requests are served with. They are created with a
+/// `ServerBuilder` and may be replaced at runtime using a `SettingsHandle`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Settings {
+    pub content_root: PathBuf,
+    pub implicit_index: bool,
+    pub directory_listing: bool,
+    pub etag: EtagMode,
+    pub limits: Limits,
+    pub timeouts: Timeouts,
+    pub compression: Compression,
+    pub mime: MimeTypes,
+    pub symlinks: SymlinkPolicy,
 }
 
-impl Server {
-    pub fn new<P: Into<PathBuf>>(
-        listener: TcpListener,
-        content_root: P,
-        implicit_index: bool,
-    ) -> Server {
+impl Default for Settings {
+    fn default() -> Self {
         Self {
-            listener,
-            content_root: content_root.into(),
-            implicit_index,
+            content_root: PathBuf::from("."),
+            implicit_index: false,
+            directory_listing: false,
+            etag: EtagMode::default(),
+            limits: Limits::default(),
+            timeouts: Timeouts::default(),
+            compression: Compression::default(),
+            mime: MimeTypes::default(),
+            symlinks: SymlinkPolicy::default(),
         }
     }
+}
 
-    pub async fn listen(&self) -> ! {
-        loop {
-            match self.listener.accept().await {
-                Err(err) => error!("Failed accepting connection: {}", err),
-                Ok((stream, addr)) => {
-                    let root = self.content_root.clone();
-                    let implicit_index = self.implicit_index;
-                    tokio::spawn(async move {
-                        debug!("Connection accepted {}", addr);
-                        Conn::new(stream, addr, root, implicit_index)
-                            .serve()
-                            .await
-                            .unwrap();
-                    });
+/// Shared handle to the settings of a server. Settings stored through it
+/// apply to every request started afterwards, requests in flight finish
+/// with the settings they started with.
+#[derive(Clone)]
+pub struct SettingsHandle(Arc<ArcSwap<Settings>>);
+
+impl SettingsHandle {
+    fn new(settings: Settings) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(settings)))
+    }
+
+    pub fn load(&self) -> Arc<Settings> {
+        self.0.load_full()
+    }
+
+    pub fn store(&self, settings: Settings) {
+        self.0.store(Arc::new(settings));
+    }
+}
+
+/// Serves requests on connections accepted from one or more listeners.
+pub struct Server {
+    /// Every listener with the settings its connections are served with.
+    listeners: Vec<(Listener, SettingsHandle)>,
+    settings: SettingsHandle,
+    handler: Arc<dyn Handler>,
+}
+
+impl Server {
+    /// Creates a builder to configure a new server.
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    /// Returns a handle to replace the settings while the server is
+    /// running.
+    pub fn settings_handle(&self) -> SettingsHandle {
+        self.settings.clone()
+    }
+
+    /// Accepts connections from another listener as well. They are served
+    /// with `settings` if given, which can then be replaced independently
+    /// using the returned handle, and with the server's settings otherwise.
+    pub fn add_listener<L: Into<Listener>>(
+        &mut self,
+        listener: L,
+        settings: Option<Settings>,
+    ) -> SettingsHandle {
+        let settings = match settings {
+            Some(s) => SettingsHandle::new(s),
+            None => self.settings.clone(),
+        };
+        self.listeners.push((listener.into(), settings.clone()));
+        settings
+    }
+
+    /// Waits for a connection on any listener. Polling starts at `next`,
+    /// which is advanced past the listener that was ready, so a busy
+    /// listener can't starve the ones after it.
+    async fn accept(&self, next: &mut usize) -> (io::Result<Accepted>, &SettingsHandle) {
+        poll_fn(|cx| {
+            let n = self.listeners.len();
+            for i in (*next..n).chain(0..*next) {
+                let (listener, settings) = &self.listeners[i];
+                if let Poll::Ready(res) = listener.poll_accept(cx) {
+                    *next = (i + 1) % n;
+                    return Poll::Ready((res, settings));
                 }
             }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Accepts and serves connections from all listeners until `shutdown`
+    /// completes. Then no new connections are accepted, idle connections
+    /// are closed and active ones may finish their current response until
+    /// the drain timeout is reached, after which they are closed forcibly.
+    pub async fn listen<F>(&self, shutdown: F) -> io::Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut conns = JoinSet::new();
+        tokio::pin!(shutdown);
+
+        let mut next = 0;
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => break,
+                Some(_) = conns.join_next(), if !conns.is_empty() => {}
+                (res, settings) = self.accept(&mut next) => match res {
+                    Err(err) => error!("Failed accepting connection: {}", err),
+                    Ok(accepted) => {
+                        let settings = settings.clone();
+                        let handler = self.handler.clone();
+                        let shutdown = shutdown_rx.clone();
+                        conns.spawn(async move {
+                            let peer = accepted.peer.clone();
+                            debug!("Connection accepted {}", peer);
+                            if let Err(err) = serve(accepted, settings, handler, shutdown).await {
+                                debug!("Connection {} closed with error: {}", peer, err);
+                            }
+                        });
+                    }
+                },
+            }
+        }
+
+        info!("Shutting down, draining {} connections ...", conns.len());
+        let _ = shutdown_tx.send(true);
+
+        let drain = async { while conns.join_next().await.is_some() {} };
+        let drain_timeout = self.settings.load().timeouts.drain_timeout;
+        if timeout(drain_timeout, drain).await.is_err() {
+            warn!(
+                "Drain timeout exceeded, closing {} connections",
+                conns.len()
+            );
+            conns.shutdown().await;
+        }
+
+        Ok(())
+    }
+
+    /// Serves a single connection accepted elsewhere, which may be any
+    /// transport such as a Unix socket, a TLS stream or an in-memory pipe.
+    /// Once `shutdown` completes, the connection is closed when idle or
+    /// after finishing its current response.
+    pub async fn serve_connection<S, F>(&self, stream: S, peer: Peer, shutdown: F) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync,
+        F: Future<Output = ()>,
+    {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let mut conn = Conn::new(
+            stream,
+            peer,
+            self.settings.clone(),
+            self.handler.clone(),
+            shutdown_rx,
+        );
+        let serve = conn.serve();
+        tokio::pin!(serve, shutdown);
+
+        tokio::select! {
+            res = &mut serve => return res,
+            _ = &mut shutdown => {}
+        }
+        let _ = shutdown_tx.send(true);
+        serve.await
+    }
+}
+
+/// Serves a connection accepted by a listener, performing the TLS handshake
+/// first if the listener uses TLS.
+async fn serve(
+    accepted: Accepted,
+    settings: SettingsHandle,
+    handler: Arc<dyn Handler>,
+    shutdown: watch::Receiver<bool>,
+) -> io::Result<()> {
+    let Accepted { stream, peer, tls } = accepted;
+    let Some(tls) = tls else {
+        return Conn::new(stream, peer, settings, handler, shutdown)
+            .serve()
+            .await;
+    };
+
+    // The handshake is bounded by the time the client has to send the
+    // request header.
+    let handshake_timeout = settings.load().timeouts.header_read_timeout;
+    let (stream, client_cert) = timeout(handshake_timeout, tls.accept(stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))??;
+    Conn::new(stream, peer, settings, handler, shutdown)
+        .client_cert(client_cert)
+        .serve()
+        .await
+}
+
+/// Configures a `Server`. Every setting is optional, by default the
+/// current directory is served.
+#[derive(Clone, Default)]
+#[must_use]
+pub struct ServerBuilder {
+    settings: Settings,
+    handler: Option<Arc<dyn Handler>>,
+    layers: Vec<Arc<dyn Middleware>>,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn content_root<P: Into<PathBuf>>(mut self, content_root: P) -> Self {
+        self.settings.content_root = content_root.into();
+        self
+    }
+
+    /// Serves `index.html` for requests to a directory containing one.
+    pub fn implicit_index(mut self, implicit_index: bool) -> Self {
+        self.settings.implicit_index = implicit_index;
+        self
+    }
+
+    pub fn directory_listing(mut self, directory_listing: bool) -> Self {
+        self.settings.directory_listing = directory_listing;
+        self
+    }
+
+    pub fn etag(mut self, etag: EtagMode) -> Self {
+        self.settings.etag = etag;
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.settings.limits = limits;
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.settings.timeouts = timeouts;
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.settings.compression = compression;
+        self
+    }
+
+    pub fn mime_types(mut self, mime: MimeTypes) -> Self {
+        self.settings.mime = mime;
+        self
+    }
+
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.settings.symlinks = symlinks;
+        self
+    }
+
+    /// Sets the handler answering requests. Defaults to `FileServer`.
+    pub fn handler<H: Handler>(mut self, handler: H) -> Self {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Adds a middleware around the handler. Middleware runs in the order
+    /// it is added: the first one sees the request first and the response
+    /// last.
+    pub fn layer<M: Middleware>(mut self, middleware: M) -> Self {
+        self.layers.push(Arc::new(middleware));
+        self
+    }
+
+    /// Returns the configured settings without creating a server, e.g. to
+    /// replace the settings of a running one.
+    pub fn into_settings(self) -> Settings {
+        self.settings
+    }
+
+    /// Creates the server accepting connections from `listener`. More
+    /// listeners are added using `Server::add_listener`.
+    pub fn build<L: Into<Listener>>(self, listener: L) -> Server {
+        let settings = SettingsHandle::new(self.settings);
+        Server {
+            listeners: vec![(listener.into(), settings.clone())],
+            settings,
+            handler: Arc::new(Chain {
+                layers: self.layers,
+                handler: self.handler.unwrap_or_else(|| Arc::new(FileServer)),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[tokio::test]
+    async fn accept_round_robin() {
+        let (a, b) = (
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+            TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        );
+        let (addr_a, addr_b) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        let mut server = Server::builder().build(a);
+        let handle_b = server.add_listener(b, Some(Settings::default()));
+
+        // The first listener always has a connection ready.
+        let mut clients = vec![];
+        for _ in 0..3 {
+            clients.push(TcpStream::connect(addr_a).await.unwrap());
+        }
+        clients.push(TcpStream::connect(addr_b).await.unwrap());
+
+        let mut next = 0;
+        let mut from_b = vec![];
+        for _ in 0..2 {
+            let (res, settings) = server.accept(&mut next).await;
+            res.unwrap();
+            from_b.push(Arc::ptr_eq(&settings.0, &handle_b.0));
         }
+        assert_eq!(from_b, [false, true]);
     }
 }
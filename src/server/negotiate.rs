@@ -0,0 +1,115 @@
+/// An element of a weighted list header like `Accept` or `Accept-Encoding`
+/// as specified in RFC 9110, Section 12.4.2.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QualityItem<'a> {
+    pub value: &'a str,
+    pub q: f32,
+}
+
+/// Parses all given header values into their items. Parameters other than
+/// the weight are dropped, items with an invalid weight are skipped.
+pub fn parse_quality_list<S: AsRef<str>>(values: &[S]) -> Vec<QualityItem<'_>> {
+    values
+        .iter()
+        .flat_map(|v| v.as_ref().split(','))
+        .filter_map(|item| {
+            let mut params = item.split(';').map(str::trim);
+            let value = params.next().filter(|v| !v.is_empty())?;
+
+            let mut q = 1.0;
+            for param in params {
+                if let Some((k, v)) = param.split_once('=') {
+                    if k.trim().eq_ignore_ascii_case("q") {
+                        q = v.trim().parse().ok().filter(|q| (0.0..=1.0).contains(q))?;
+                    }
+                }
+            }
+
+            Some(QualityItem { value, q })
+        })
+        .collect()
+}
+
+/// Picks the media type from `offers` which the client prefers according
+/// to its `Accept` header. Every offer is weighted by the most specific
+/// range matching it. Ties and a missing header favour the earlier offer.
+pub fn preferred_media_type<'a, S: AsRef<str>>(
+    accept: Option<&[S]>,
+    offers: &[&'a str],
+) -> &'a str {
+    let Some(accept) = accept else {
+        return offers[0];
+    };
+    let ranges = parse_quality_list(accept);
+
+    let mut best = (offers[0], 0.0);
+    for offer in offers {
+        let (ty, _) = offer.split_once('/').unwrap_or((offer, ""));
+
+        let q = ranges
+            .iter()
+            .filter_map(|r| {
+                let specificity = if r.value.eq_ignore_ascii_case(offer) {
+                    2
+                } else if r.value.eq_ignore_ascii_case(&format!("{ty}/*")) {
+                    1
+                } else if r.value == "*/*" {
+                    0
+                } else {
+                    return None;
+                };
+                Some((specificity, r.q))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map_or(0.0, |(_, q)| q);
+
+        if q > best.1 {
+            best = (offer, q);
+        }
+    }
+
+    best.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quality_list() {
+        assert_eq!(
+            parse_quality_list(&["gzip;q=0.5, br", "identity; q=0, x;q=2"]),
+            [
+                QualityItem {
+                    value: "gzip",
+                    q: 0.5
+                },
+                QualityItem {
+                    value: "br",
+                    q: 1.0
+                },
+                QualityItem {
+                    value: "identity",
+                    q: 0.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn media_type() {
+        let offers = ["text/html", "application/json"];
+        let pick = |accept: &str| preferred_media_type(Some(&[accept]), &offers);
+
+        assert_eq!(preferred_media_type::<&str>(None, &offers), "text/html");
+        assert_eq!(pick("application/json"), "application/json");
+        assert_eq!(pick("text/html, application/json"), "text/html");
+        assert_eq!(
+            pick("text/html;q=0.9, application/json"),
+            "application/json"
+        );
+        assert_eq!(pick("application/*, */*;q=0.1"), "application/json");
+        assert_eq!(pick("*/*"), "text/html");
+        assert_eq!(pick("image/png"), "text/html");
+    }
+}
@@ -0,0 +1,339 @@
+use super::{
+    body::{Body, Framing, Reader},
+    error::RequestError,
+    listener::Peer,
+    request::{HeaderMap, Method, Request, Version},
+    uri::Uri,
+    Settings,
+};
+use serde::Deserialize;
+use std::{io, sync::Arc};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+/// Number of empty lines which are skipped before a request line.
+const MAX_LEADING_EMPTY_LINES: usize = 4;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct Limits {
+    /// Maximum length of the request line in bytes.
+    pub max_request_line: usize,
+    /// Maximum number of header fields.
+    pub max_headers: usize,
+    /// Maximum size of the whole header section in bytes.
+    pub max_header_bytes: usize,
+    /// Maximum size of a request body in bytes.
+    pub max_body_size: usize,
+    /// Maximum number of requests served on one connection before it is
+    /// closed.
+    pub max_requests_per_connection: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_request_line: 8 * 1024,
+            max_headers: 100,
+            max_header_bytes: 64 * 1024,
+            max_body_size: 1024 * 1024,
+            max_requests_per_connection: 1000,
+        }
+    }
+}
+
+pub struct RequestParser<'c> {
+    r: &'c mut Reader<'c>,
+    buf: Vec<u8>,
+    limits: Limits,
+    settings: Arc<Settings>,
+}
+
+impl<'c> RequestParser<'c> {
+    /// Creates a parser for requests served with `settings`.
+    pub fn new(r: &'c mut Reader<'c>, settings: Arc<Settings>) -> Self {
+        Self {
+            r,
+            buf: Vec::new(),
+            limits: settings.limits,
+            settings,
+        }
+    }
+
+    /// Reads the next request from the connection. Returns `None` if the
+    /// connection has been closed before a new request was started.
+    pub async fn parse(mut self) -> Result<Option<Request<'c>>, RequestError> {
+        let Some((version, uri, method)) = self.parse_head().await? else {
+            return Ok(None);
+        };
+
+        let header = self.parse_header().await?;
+
+        if version >= Version::Http11 && header.get("host").map_or(0, |v| v.len()) != 1 {
+            return Err(RequestError::BadRequest(
+                "exactly one Host header is required",
+            ));
+        }
+
+        let keep_alive = version.keep_alive(&header);
+        let framing = Framing::from_header(version, &header)?;
+        let body = Body::new(self.r, framing, self.limits.max_body_size)?;
+
+        Ok(Some(Request {
+            method,
+            version,
+            uri,
+            header,
+            body,
+            keep_alive,
+            params: vec![],
+            settings: self.settings,
+            peer: Peer::Unknown,
+            client_cert: None,
+        }))
+    }
+
+    pub async fn parse_head(&mut self) -> Result<Option<(Version, Uri, Method)>, RequestError> {
+        for _ in 0..=MAX_LEADING_EMPTY_LINES {
+            if self.r.fill_buf().await?.is_empty() {
+                return Ok(None);
+            }
+
+            self.buf.clear();
+            read_line(
+                self.r,
+                &mut self.buf,
+                self.limits.max_request_line,
+                RequestError::UriTooLong,
+            )
+            .await?;
+
+            if !self.buf.is_empty() {
+                break;
+            }
+        }
+
+        let line = std::str::from_utf8(&self.buf)
+            .map_err(|_| RequestError::BadRequest("request line is not valid UTF-8"))?;
+
+        let mut split = line.split(' ');
+        let (Some(method), Some(target), Some(proto), None) =
+            (split.next(), split.next(), split.next(), split.next())
+        else {
+            return Err(RequestError::BadRequest("malformed request line"));
+        };
+
+        if !is_token(method) {
+            return Err(RequestError::BadRequest("invalid method"));
+        }
+
+        let version = parse_version(proto)?;
+        let method = Method::from(method);
+        let uri = Uri::parse(target, &method)?;
+
+        Ok(Some((version, uri, method)))
+    }
+
+    pub async fn parse_header(&mut self) -> Result<HeaderMap, RequestError> {
+        let mut m = HeaderMap::new();
+        let mut remaining = self.limits.max_header_bytes;
+        let mut count = 0;
+
+        loop {
+            self.buf.clear();
+            let n = read_line(
+                self.r,
+                &mut self.buf,
+                remaining,
+                RequestError::RequestHeaderFieldsTooLarge,
+            )
+            .await?;
+            remaining -= n;
+
+            if self.buf.is_empty() {
+                break;
+            }
+
+            count += 1;
+            if count > self.limits.max_headers {
+                return Err(RequestError::RequestHeaderFieldsTooLarge);
+            }
+
+            let (key, value) = parse_field_line(&self.buf)?;
+            m.insert(key, value);
+        }
+
+        Ok(m)
+    }
+}
+
+/// Reads a line terminated by LF into `buf` and strips the line ending.
+/// Returns the number of bytes consumed from `r`, or `too_long` if no line
+/// ending was found within `max` bytes.
+pub async fn read_line<R>(
+    r: &mut R,
+    buf: &mut Vec<u8>,
+    max: usize,
+    too_long: RequestError,
+) -> Result<usize, RequestError>
+where
+    R: AsyncBufRead + Unpin + ?Sized,
+{
+    let n = r.take(max as u64).read_until(b'\n', buf).await?;
+
+    if !buf.ends_with(b"\n") {
+        if n < max {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        return Err(too_long);
+    }
+
+    buf.pop();
+    if buf.ends_with(b"\r") {
+        buf.pop();
+    }
+
+    Ok(n)
+}
+
+/// Splits a field line into name and value as specified in RFC 9112,
+/// Section 5.
+pub fn parse_field_line(line: &[u8]) -> Result<(String, String), RequestError> {
+    if line.starts_with(b" ") || line.starts_with(b"\t") {
+        return Err(RequestError::BadRequest("obsolete line folding"));
+    }
+
+    let Some(colon) = line.iter().position(|b| *b == b':') else {
+        return Err(RequestError::BadRequest("header field without colon"));
+    };
+
+    let key = std::str::from_utf8(&line[..colon])
+        .ok()
+        .filter(|k| is_token(k))
+        .ok_or(RequestError::BadRequest("invalid header field name"))?;
+
+    let value = &line[colon + 1..];
+    if value.iter().any(|b| *b != b'\t' && b.is_ascii_control()) {
+        return Err(RequestError::BadRequest("invalid header field value"));
+    }
+
+    Ok((key.into(), String::from_utf8_lossy(value).trim().into()))
+}
+
+fn parse_version(proto: &str) -> Result<Version, RequestError> {
+    let Some((major, minor)) = proto
+        .strip_prefix("HTTP/")
+        .and_then(|v| v.split_once('.'))
+        .filter(|(major, minor)| {
+            major.len() == 1
+                && minor.len() == 1
+                && major
+                    .bytes()
+                    .chain(minor.bytes())
+                    .all(|b| b.is_ascii_digit())
+        })
+    else {
+        return Err(RequestError::BadRequest("invalid protocol version"));
+    };
+
+    match (major, minor) {
+        ("1", "0") => Ok(Version::Http10),
+        // Higher minor versions are answered as the highest supported one.
+        ("1", _) => Ok(Version::Http11),
+        _ => Err(RequestError::HttpVersionNotSupported),
+    }
+}
+
+/// Checks if `v` is a token as defined in RFC 9110, Section 5.6.2.
+fn is_token(v: &str) -> bool {
+    !v.is_empty()
+        && v.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::statuscode::StatusCode;
+
+    fn settings(limits: Limits) -> Arc<Settings> {
+        Arc::new(Settings {
+            limits,
+            ..Default::default()
+        })
+    }
+
+    async fn parse_err(raw: &'static [u8], limits: Limits) -> RequestError {
+        let mut r = raw;
+        match RequestParser::new(&mut r, settings(limits)).parse().await {
+            Ok(_) => panic!("expected error for {}", String::from_utf8_lossy(raw)),
+            Err(err) => err,
+        }
+    }
+
+    #[tokio::test]
+    async fn parse_request() {
+        let mut raw: &[u8] =
+            b"\r\nGET /index.html HTTP/1.1\r\nHost: localhost\r\nX-Foo:  bar \r\n\r\n";
+        let req = RequestParser::new(&mut raw, settings(Limits::default()))
+            .parse()
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(req.method, Method::Get);
+        assert_eq!(req.version, Version::Http11);
+        assert_eq!(req.uri.path, "/index.html");
+        assert_eq!(req.header.get("x-foo").unwrap(), &vec!["bar".to_string()]);
+
+        let mut raw: &[u8] = b"";
+        let req = RequestParser::new(&mut raw, settings(Limits::default()))
+            .parse()
+            .await;
+        assert!(req.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn parse_errors() {
+        let limits = Limits {
+            max_request_line: 32,
+            max_headers: 2,
+            max_header_bytes: 64,
+            ..Default::default()
+        };
+
+        let cases: &[(&'static [u8], Option<StatusCode>)] = &[
+            (b"GET /\r\n\r\n", Some(StatusCode::BadRequest)),
+            (b"GET  / HTTP/1.1\r\n\r\n", Some(StatusCode::BadRequest)),
+            (b"G(T / HTTP/1.1\r\n\r\n", Some(StatusCode::BadRequest)),
+            (b"GET / HTTP/1.1\r\n\r\n", Some(StatusCode::BadRequest)),
+            (b"GET / HTTP/1.1\r\nHost: a\r\nHost : b\r\n\r\n", Some(StatusCode::BadRequest)),
+            (b"GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n", Some(StatusCode::BadRequest)),
+            (b"GET / HTTP/2.0\r\n\r\n", Some(StatusCode::HttpVersionNotSupported)),
+            (b"GET / HTTP/x\r\n\r\n", Some(StatusCode::BadRequest)),
+            (
+                b"GET /a-very-long-path-exceeding-the-limit HTTP/1.1\r\n\r\n",
+                Some(StatusCode::UriTooLong),
+            ),
+            (
+                b"GET / HTTP/1.1\r\nHost: a\r\nA: b\r\nC: d\r\n\r\n",
+                Some(StatusCode::RequestHeaderFieldsTooLarge),
+            ),
+            (
+                b"GET / HTTP/1.1\r\nHost: a\r\nA: a-very-long-header-value-exceeding-the-header-section-limit\r\n\r\n",
+                Some(StatusCode::RequestHeaderFieldsTooLarge),
+            ),
+            (b"GET / HTTP/1.1\r\nHost: a\r\n", None),
+        ];
+
+        for (raw, status) in cases {
+            let err = parse_err(raw, limits).await;
+            assert_eq!(
+                err.status_code(),
+                *status,
+                "{}",
+                String::from_utf8_lossy(raw)
+            );
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use std::ops::RangeInclusive;
+
+/// Upper limit of ranges accepted in a single request. Requests for more
+/// ranges than this are answered with the full representation.
+const MAX_RANGES: usize = 32;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Ranges {
+    /// The `Range` header should be ignored and the full content served.
+    Ignore,
+    Satisfiable(Vec<RangeInclusive<u64>>),
+    Unsatisfiable,
+}
+
+/// Parses the value of a `Range` header against a representation of
+/// `len` bytes as specified in RFC 9110, Section 14.1.
+pub fn parse_range(value: &str, len: u64) -> Ranges {
+    let Some((unit, set)) = value.split_once('=') else {
+        return Ranges::Unsatisfiable;
+    };
+
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return Ranges::Ignore;
+    }
+
+    let mut ranges = vec![];
+
+    for spec in set.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let Some((first, last)) = spec.split_once('-') else {
+            return Ranges::Unsatisfiable;
+        };
+
+        let range = match (first.trim(), last.trim()) {
+            ("", "") => return Ranges::Unsatisfiable,
+            ("", suffix) => {
+                let Ok(suffix) = suffix.parse::<u64>() else {
+                    return Ranges::Unsatisfiable;
+                };
+                if suffix == 0 || len == 0 {
+                    continue;
+                }
+                len.saturating_sub(suffix)..=len - 1
+            }
+            (first, last) => {
+                let Ok(first) = first.parse::<u64>() else {
+                    return Ranges::Unsatisfiable;
+                };
+                let last = match last {
+                    "" => u64::MAX,
+                    v => match v.parse::<u64>() {
+                        Ok(v) if v >= first => v,
+                        _ => return Ranges::Unsatisfiable,
+                    },
+                };
+                if first >= len {
+                    continue;
+                }
+                first..=last.min(len - 1)
+            }
+        };
+
+        ranges.push(range);
+    }
+
+    if ranges.len() > MAX_RANGES {
+        return Ranges::Ignore;
+    }
+
+    if ranges.is_empty() {
+        return Ranges::Unsatisfiable;
+    }
+
+    Ranges::Satisfiable(ranges)
+}
+
+pub fn content_range(range: &RangeInclusive<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start(), range.end(), len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_range_test() {
+        assert_eq!(
+            parse_range("bytes=0-499", 1000),
+            Ranges::Satisfiable(vec![0..=499])
+        );
+        assert_eq!(
+            parse_range("bytes=500-", 1000),
+            Ranges::Satisfiable(vec![500..=999])
+        );
+        assert_eq!(
+            parse_range("bytes=-200", 1000),
+            Ranges::Satisfiable(vec![800..=999])
+        );
+        assert_eq!(
+            parse_range("bytes=-2000", 1000),
+            Ranges::Satisfiable(vec![0..=999])
+        );
+        assert_eq!(
+            parse_range("bytes=900-1999", 1000),
+            Ranges::Satisfiable(vec![900..=999])
+        );
+        assert_eq!(
+            parse_range("bytes=0-0, 2000-3000, -1", 1000),
+            Ranges::Satisfiable(vec![0..=0, 999..=999])
+        );
+
+        assert_eq!(parse_range("bytes=1000-", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=5-3", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=a-b", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("bytes=", 1000), Ranges::Unsatisfiable);
+        assert_eq!(parse_range("items=0-1", 1000), Ranges::Ignore);
+    }
+}
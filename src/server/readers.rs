@@ -5,8 +5,11 @@ use std::{
 };
 use tokio::io::{AsyncRead, ReadBuf};
 
+/// Provides the size of a body up front, if it is known. Bodies of unknown
+/// size are sent using chunked transfer-encoding.
+#[allow(clippy::len_without_is_empty)]
 pub trait ContentLength {
-    fn len(&self) -> usize;
+    fn len(&self) -> Option<usize>;
 }
 
 #[derive(Default)]
@@ -63,13 +66,13 @@ where
 }
 
 impl ContentLength for NoOp {
-    fn len(&self) -> usize {
-        0
+    fn len(&self) -> Option<usize> {
+        Some(0)
     }
 }
 
 impl ContentLength for Text {
-    fn len(&self) -> usize {
-        self.v.len()
+    fn len(&self) -> Option<usize> {
+        Some(self.v.len())
     }
 }
@@ -1,12 +1,9 @@
+use super::{body::Body, listener::Peer, tls::ClientCert, uri::Uri, Settings};
 use core::fmt;
-use std::{
-    cell::{Ref, RefCell},
-    collections::HashMap,
-    path::PathBuf,
-    vec,
-};
+use std::{collections::HashMap, sync::Arc, vec};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Method {
     Get,
     Head,
@@ -51,8 +48,49 @@ impl From<&str> for Method {
     }
 }
 
-#[derive(Default, Debug)]
-pub struct HeaderMap(HashMap<String, RefCell<Vec<String>>>);
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
+pub enum Version {
+    Http10,
+    #[default]
+    Http11,
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Http10 => write!(f, "HTTP/1.0"),
+            Self::Http11 => write!(f, "HTTP/1.1"),
+        }
+    }
+}
+
+impl Version {
+    /// Decides from the `Connection` header whether the client wants to
+    /// keep the connection open. HTTP/1.1 defaults to persistent
+    /// connections, HTTP/1.0 has to ask for them explicitly.
+    pub fn keep_alive(&self, header: &HeaderMap) -> bool {
+        let has_option = |name: &str| {
+            header.get("connection").is_some_and(|values| {
+                values
+                    .iter()
+                    .flat_map(|v| v.split(','))
+                    .any(|v| v.trim().eq_ignore_ascii_case(name))
+            })
+        };
+
+        if has_option("close") {
+            return false;
+        }
+        match self {
+            Self::Http10 => has_option("keep-alive"),
+            Self::Http11 => true,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct HeaderMap(HashMap<String, Vec<String>>);
 
 impl HeaderMap {
     pub fn new() -> Self {
@@ -61,13 +99,17 @@ impl HeaderMap {
 
     pub fn insert<K: AsRef<str>, V: Into<String>>(&mut self, key: K, value: V) {
         let key: String = canonicalize(key.as_ref());
-        let entry = self.0.entry(key).or_insert(RefCell::new(vec![]));
-        entry.borrow_mut().push(value.into());
+        self.0.entry(key).or_default().push(value.into());
     }
 
-    pub fn get<K: AsRef<str>>(&self, key: K) -> Option<Ref<Vec<String>>> {
+    pub fn get<K: AsRef<str>>(&self, key: K) -> Option<&Vec<String>> {
         let key = canonicalize(key.as_ref());
-        self.0.get(&key).map(|v| v.borrow())
+        self.0.get(&key)
+    }
+
+    pub fn remove<K: AsRef<str>>(&mut self, key: K) -> Option<Vec<String>> {
+        let key = canonicalize(key.as_ref());
+        self.0.remove(&key)
     }
 }
 
@@ -110,7 +152,7 @@ impl IntoIterator for HeaderMap {
         let mut res = vec![];
 
         for (k, vs) in self.0.iter() {
-            for v in vs.borrow().iter() {
+            for v in vs.iter() {
                 res.push((k.to_string(), v.to_string()));
             }
         }
@@ -119,20 +161,69 @@ impl IntoIterator for HeaderMap {
     }
 }
 
+/// A request read from a connection. The body is read on demand.
 #[derive(Debug)]
-#[allow(dead_code)]
-pub struct Request {
+#[non_exhaustive]
+pub struct Request<'c> {
     pub method: Method,
-    pub proto: String,
-    pub path: PathBuf,
+    pub version: Version,
+    pub uri: Uri,
     pub header: HeaderMap,
-    pub body: Option<Vec<u8>>,
+    pub body: Body<'c>,
+    /// Whether the connection may be reused after answering this request.
+    pub keep_alive: bool,
+    pub(crate) params: Vec<(String, String)>,
+    pub(crate) settings: Arc<Settings>,
+    pub(crate) peer: Peer,
+    pub(crate) client_cert: Option<Arc<ClientCert>>,
+}
+
+impl Request<'_> {
+    /// Returns the value of the path parameter `name` of the matched
+    /// route.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Returns the client which sent this request.
+    pub fn peer(&self) -> &Peer {
+        &self.peer
+    }
+
+    /// Returns the verified certificate the client presented in the TLS
+    /// handshake, if any.
+    pub fn client_cert(&self) -> Option<&ClientCert> {
+        self.client_cert.as_deref()
+    }
+
+    /// Returns the settings this request is served with.
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn keep_alive() {
+        let header = |v: &str| {
+            let mut h = HeaderMap::new();
+            h.insert("connection", v);
+            h
+        };
+
+        assert!(Version::Http11.keep_alive(&HeaderMap::new()));
+        assert!(!Version::Http11.keep_alive(&header("Upgrade, Close")));
+        assert!(!Version::Http10.keep_alive(&HeaderMap::new()));
+        assert!(Version::Http10.keep_alive(&header("Keep-Alive")));
+        assert!(!Version::Http10.keep_alive(&header("keep-alive, close")));
+    }
+
     #[test]
     fn canonicalize_test() {
         assert_eq!(canonicalize("content-type"), "Content-Type");
@@ -0,0 +1,131 @@
+use super::statuscode::StatusCode;
+use serde::Deserialize;
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+/// Defines how symbolic links below the content root are treated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SymlinkPolicy {
+    /// Symlinks are followed, even if they point outside of the content
+    /// root.
+    #[default]
+    Follow,
+    /// Requests resolving through any symlink are answered with
+    /// `403 Forbidden`.
+    Deny,
+    /// Symlinks are only followed if the link and its target are owned by
+    /// the same user.
+    IfOwnerMatches,
+}
+
+/// Maps the decoded request path to a file or directory below `root`.
+/// Errors carry the status code the request should be answered with.
+pub async fn resolve(
+    root: &Path,
+    path: &str,
+    implicit_index: bool,
+    symlinks: SymlinkPolicy,
+) -> Result<PathBuf, StatusCode> {
+    let mut rel = normalize(path).ok_or(StatusCode::BadRequest)?;
+
+    if implicit_index && root.join(&rel).join("index.html").is_file() {
+        rel.push("index.html");
+    }
+
+    check_symlinks(root, &rel, symlinks).await?;
+
+    Ok(root.join(rel))
+}
+
+/// Resolves `.` and `..` segments of `path` lexically and returns the
+/// result relative to the content root. Returns `None` if the path would
+/// leave the content root or contains segments which could be interpreted
+/// differently by the file system.
+pub fn normalize(path: &str) -> Option<PathBuf> {
+    let mut res = PathBuf::new();
+
+    for seg in path.split('/') {
+        if seg.contains(['\\', '\0']) {
+            return None;
+        }
+
+        match seg {
+            "" | "." => {}
+            ".." => {
+                if !res.pop() {
+                    return None;
+                }
+            }
+            seg => res.push(seg),
+        }
+    }
+
+    Some(res)
+}
+
+/// Walks every component of `rel` below `root` and applies the symlink
+/// policy to each link found. Components which do not exist end the walk,
+/// opening the file reports them as not found later on.
+async fn check_symlinks(root: &Path, rel: &Path, policy: SymlinkPolicy) -> Result<(), StatusCode> {
+    if policy == SymlinkPolicy::Follow {
+        return Ok(());
+    }
+
+    let mut cur = root.to_path_buf();
+    for component in rel.components() {
+        cur.push(component);
+
+        let Ok(meta) = fs::symlink_metadata(&cur).await else {
+            break;
+        };
+        if !meta.file_type().is_symlink() {
+            continue;
+        }
+
+        if policy == SymlinkPolicy::Deny {
+            return Err(StatusCode::Forbidden);
+        }
+
+        let Ok(target) = fs::metadata(&cur).await else {
+            break;
+        };
+        if !same_owner(&meta, &target) {
+            return Err(StatusCode::Forbidden);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn same_owner(link: &Metadata, target: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    link.uid() == target.uid()
+}
+
+#[cfg(not(unix))]
+fn same_owner(_: &Metadata, _: &Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(normalize("/"), Some(PathBuf::new()));
+        assert_eq!(normalize("/a/./b//c/"), Some(PathBuf::from("a/b/c")));
+        assert_eq!(normalize("/a/../b"), Some(PathBuf::from("b")));
+        assert_eq!(normalize("/a/b/../../c"), Some(PathBuf::from("c")));
+
+        assert_eq!(normalize("/.."), None);
+        assert_eq!(normalize("/a/../../etc/passwd"), None);
+        assert_eq!(normalize("/..\\..\\etc\\passwd"), None);
+        assert_eq!(normalize("/index.html\0.txt"), None);
+    }
+}
@@ -1,24 +1,30 @@
 use super::{
+    handler::{BoxBody, Response},
     readers::{ContentLength, NoOp, Text},
-    request::HeaderMap,
+    request::{HeaderMap, Version},
     statuscode::StatusCode,
 };
-use anyhow::Result;
-use tokio::{
-    io::{self, AsyncRead, AsyncWriteExt},
-    net::TcpStream,
-};
+use std::time::Duration;
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tracing::debug;
 
+const CHUNK_SIZE: usize = 16 * 1024;
+
 struct Body<B> {
     body: B,
-    size: usize,
+    /// `None` if the size is not known before the body has been read.
+    size: Option<usize>,
 }
 
 pub struct ResponseBuilder<B = NoOp> {
     status_code: StatusCode,
     header: Option<HeaderMap>,
+    trailer: Option<HeaderMap>,
     body: Body<B>,
+    omit_body: bool,
+    version: Version,
+    keep_alive: bool,
+    keep_alive_timeout: Option<Duration>,
 }
 
 impl ResponseBuilder {
@@ -26,44 +32,172 @@ impl ResponseBuilder {
         Self {
             status_code: Default::default(),
             header: Default::default(),
+            trailer: Default::default(),
             body: Body {
                 body: NoOp,
-                size: 0,
+                size: Some(0),
             },
+            omit_body: false,
+            version: Default::default(),
+            keep_alive: true,
+            keep_alive_timeout: None,
         }
     }
 }
 
-impl ResponseBuilder {
-    pub fn body<B>(self, body: B, size: usize) -> ResponseBuilder<B>
+impl Default for ResponseBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B> ResponseBuilder<B> {
+    pub fn body<R>(self, body: R, size: usize) -> ResponseBuilder<R>
+    where
+        R: AsyncRead,
+    {
+        self.with_body(body, Some(size))
+    }
+
+    /// Sets a body of unknown size. It is sent using chunked
+    /// transfer-encoding to HTTP/1.1 clients. For HTTP/1.0 clients, the end
+    /// of the body is signalled by closing the connection.
+    pub fn body_stream<R>(self, body: R) -> ResponseBuilder<R>
+    where
+        R: AsyncRead,
+    {
+        self.with_body(body, None)
+    }
+
+    pub fn body_with_len<R>(self, body: R) -> ResponseBuilder<R>
     where
-        B: AsyncRead,
+        R: AsyncRead,
+        R: ContentLength,
     {
+        let ln = body.len();
+        self.with_body(body, ln)
+    }
+
+    /// Sets a plain text body. No content type is set.
+    pub fn text<S: Into<String>>(self, text: S) -> ResponseBuilder<Text> {
+        let body = Text::from(text);
+        let size = body.len();
+        self.with_body(body, size)
+    }
+
+    /// Erases the body type to return the response from a handler.
+    pub fn boxed(self) -> Response
+    where
+        B: AsyncRead + Send + 'static,
+    {
+        let body: BoxBody = Box::pin(self.body.body);
         ResponseBuilder {
-            body: Body { body, size },
+            body: Body {
+                body,
+                size: self.body.size,
+            },
             header: self.header,
+            trailer: self.trailer,
             status_code: self.status_code,
+            omit_body: self.omit_body,
+            version: self.version,
+            keep_alive: self.keep_alive,
+            keep_alive_timeout: self.keep_alive_timeout,
         }
     }
 
-    pub fn body_with_len<B>(self, body: B) -> ResponseBuilder<B>
-    where
-        B: AsyncRead,
-        B: ContentLength,
-    {
-        let ln = body.len();
-        self.body(body, ln)
+    pub fn status(&self) -> StatusCode {
+        self.status_code
+    }
+
+    /// Returns the values of the response header `key`.
+    pub fn get_header<K: AsRef<str>>(&self, key: K) -> Option<&Vec<String>> {
+        self.header.as_ref()?.get(key)
+    }
+
+    /// Sets the response header `key`, replacing all its previous values.
+    pub fn set_header<K: AsRef<str>, V: Into<String>>(self, key: K, value: V) -> Self {
+        let mut header = self.header.unwrap_or_default();
+        header.remove(&key);
+        header.insert(key, value);
+        Self {
+            header: Some(header),
+            ..self
+        }
+    }
+
+    /// Whether the connection stays open after sending this response,
+    /// which depends on the version set.
+    pub(crate) fn keeps_alive(&self) -> bool {
+        self.keep_alive && !self.delimited_by_close()
+    }
+
+    /// 204 and 304 responses never have a body, and a Content-Length would
+    /// have to be the one of the selected representation, RFC 9110,
+    /// Section 8.6.
+    fn bodiless(&self) -> bool {
+        matches!(
+            self.status_code,
+            StatusCode::NoContent | StatusCode::NotModified
+        )
+    }
+
+    fn chunked(&self) -> bool {
+        !self.bodiless() && self.body.size.is_none() && self.version >= Version::Http11
+    }
+
+    /// Without chunked encoding, the end of a body of unknown size can only
+    /// be signalled by closing the connection.
+    fn delimited_by_close(&self) -> bool {
+        !self.bodiless() && self.body.size.is_none() && !self.chunked()
+    }
+
+    fn with_body<R>(self, body: R, size: Option<usize>) -> ResponseBuilder<R> {
+        ResponseBuilder {
+            body: Body { body, size },
+            header: self.header,
+            trailer: self.trailer,
+            status_code: self.status_code,
+            omit_body: self.omit_body,
+            version: self.version,
+            keep_alive: self.keep_alive,
+            keep_alive_timeout: self.keep_alive_timeout,
+        }
     }
-}
 
-impl<B> ResponseBuilder<B> {
     pub fn status_code(self, status_code: StatusCode) -> ResponseBuilder<Text> {
         let body = Text::from(status_code.to_string());
         let size = body.len();
         ResponseBuilder {
             status_code,
-            header: self.header,
-            body: Body { body, size },
+            ..self.with_body(body, size)
+        }
+    }
+
+    /// Write status line and headers as usual but skip the body (for HEAD).
+    pub fn omit_body(self, omit_body: bool) -> Self {
+        Self { omit_body, ..self }
+    }
+
+    /// Sets the protocol version of the request which is answered. It is
+    /// used in the status line and decides how bodies of unknown size are
+    /// framed.
+    pub fn version(self, version: Version) -> Self {
+        Self { version, ..self }
+    }
+
+    /// Sets whether the connection stays open after this response. This is
+    /// announced to the client with the `Connection` header.
+    pub fn keep_alive(self, keep_alive: bool) -> Self {
+        Self { keep_alive, ..self }
+    }
+
+    /// Sets how long an idle persistent connection is kept open. It is
+    /// announced with the `Keep-Alive` header if the connection stays open.
+    pub fn keep_alive_timeout(self, timeout: Duration) -> Self {
+        Self {
+            keep_alive_timeout: Some(timeout),
+            ..self
         }
     }
 
@@ -87,14 +221,35 @@ impl<B> ResponseBuilder<B> {
         }
     }
 
-    pub async fn send(mut self, stream: &mut TcpStream) -> Result<()>
+    /// Adds a trailer field which is sent after the last chunk of a body of
+    /// unknown size. Trailers are dropped for bodies with a known size.
+    pub fn add_trailer<K: AsRef<str>, V: Into<String>>(
+        self,
+        key: K,
+        value: V,
+    ) -> ResponseBuilder<B> {
+        let mut trailer = self.trailer.unwrap_or_default();
+        trailer.insert(key, value);
+        Self {
+            trailer: Some(trailer),
+            ..self
+        }
+    }
+
+    pub async fn send<W>(mut self, stream: &mut W) -> io::Result<()>
     where
         B: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
     {
+        let bodiless = self.bodiless();
+        let chunked = self.chunked();
+        let delimited_by_close = self.delimited_by_close();
+
         stream
             .write_all(
                 format!(
-                    "HTTP/1.1 {} {}\r\n",
+                    "{} {} {}\r\n",
+                    self.version,
                     self.status_code.code(),
                     self.status_code
                 )
@@ -108,14 +263,128 @@ impl<B> ResponseBuilder<B> {
             }
         }
 
-        stream
-            .write_all(format!("Content-Length: {}\r\n", self.body.size).as_bytes())
-            .await?;
+        match self.body.size {
+            _ if bodiless => {}
+            Some(size) => {
+                stream
+                    .write_all(format!("Content-Length: {size}\r\n").as_bytes())
+                    .await?;
+            }
+            None if chunked => {
+                stream.write_all(b"Transfer-Encoding: chunked\r\n").await?;
+                if let Some(trailer) = &self.trailer {
+                    let names: Vec<_> = trailer.clone().into_iter().map(|(k, _)| k).collect();
+                    stream
+                        .write_all(format!("Trailer: {}\r\n", names.join(", ")).as_bytes())
+                        .await?;
+                }
+            }
+            None => {}
+        }
+
+        if !self.keep_alive || delimited_by_close {
+            stream.write_all(b"Connection: close\r\n").await?;
+        } else {
+            if self.version == Version::Http10 {
+                stream.write_all(b"Connection: keep-alive\r\n").await?;
+            }
+            if let Some(timeout) = self.keep_alive_timeout {
+                stream
+                    .write_all(format!("Keep-Alive: timeout={}\r\n", timeout.as_secs()).as_bytes())
+                    .await?;
+            }
+        }
+
         stream.write_all(b"\r\n").await?;
-        io::copy(&mut self.body.body, stream).await?;
+
+        if !self.omit_body && !bodiless {
+            if chunked {
+                copy_chunked(&mut self.body.body, stream, self.trailer).await?;
+            } else {
+                io::copy(&mut self.body.body, stream).await?;
+            }
+        }
+
+        if delimited_by_close {
+            stream.shutdown().await?;
+        }
 
         debug!("Response served!");
 
         Ok(())
     }
 }
+
+/// Copies `r` to `w` using the chunked transfer-coding as specified in
+/// RFC 9112, Section 7.1, followed by the optional trailer section.
+async fn copy_chunked<R, W>(r: &mut R, w: &mut W, trailer: Option<HeaderMap>) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; CHUNK_SIZE];
+
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+
+        w.write_all(format!("{n:x}\r\n").as_bytes()).await?;
+        w.write_all(&buf[..n]).await?;
+        w.write_all(b"\r\n").await?;
+    }
+
+    w.write_all(b"0\r\n").await?;
+    if let Some(trailer) = trailer {
+        for (k, v) in trailer.into_iter() {
+            w.write_all(format!("{k}: {v}\r\n").as_bytes()).await?;
+        }
+    }
+    w.write_all(b"\r\n").await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn copy_chunked_test() {
+        let mut body = Text::from("hello world");
+        let mut out = vec![];
+
+        let mut trailer = HeaderMap::new();
+        trailer.insert("x-checksum", "abc");
+
+        copy_chunked(&mut body, &mut out, Some(trailer))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "b\r\nhello world\r\n0\r\nX-Checksum: abc\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn keeps_alive() {
+        let res = ResponseBuilder::new().body_stream(NoOp);
+        assert!(res.version(Version::Http11).keeps_alive());
+
+        // HTTP/1.0 has no chunked encoding, so the body ends with the
+        // connection.
+        let res = ResponseBuilder::new().body_stream(NoOp);
+        assert!(!res.version(Version::Http10).keeps_alive());
+        let res = ResponseBuilder::new().body(NoOp, 0);
+        assert!(res.version(Version::Http10).keeps_alive());
+        let res = ResponseBuilder::new()
+            .status_code(StatusCode::NotModified)
+            .body_stream(NoOp);
+        assert!(res.version(Version::Http10).keeps_alive());
+
+        let res = ResponseBuilder::new().keep_alive(false);
+        assert!(!res.keeps_alive());
+    }
+}
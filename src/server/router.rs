@@ -0,0 +1,257 @@
+use super::{
+    handler::{BoxFuture, Handler, Response},
+    request::{Method, Request},
+    response::ResponseBuilder,
+    statuscode::StatusCode,
+};
+
+/// Dispatches requests to handlers by method and path.
+///
+/// Patterns are matched segment by segment against the decoded request
+/// path. A segment starting with `:` matches any single segment, one
+/// starting with `*` matches all remaining segments and may only appear
+/// last. Matched values are available using `Request::param`:
+///
+/// ```
+/// use http_server::{handler_fn, FileServer, ResponseBuilder, Router};
+///
+/// let router = Router::new()
+///     .get(
+///         "/api/items/:id",
+///         handler_fn(|req| {
+///             Box::pin(async move {
+///                 let id = req.param("id").unwrap_or_default().to_string();
+///                 ResponseBuilder::new().text(id).boxed()
+///             })
+///         }),
+///     )
+///     .fallback(FileServer);
+/// ```
+///
+/// Routes are tried in the order they were added. `GET` routes also answer
+/// `HEAD` requests. If a path matches routes for other methods only, the
+/// request is answered with `405 Method Not Allowed`, requests matching no
+/// route at all are passed to the fallback, which answers with
+/// `404 Not Found` unless set.
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Box<dyn Handler>,
+}
+
+struct Route {
+    method: Method,
+    pattern: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Rest(String),
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self {
+            routes: vec![],
+            fallback: Box::new(NotFound),
+        }
+    }
+
+    /// Adds a route for requests with `method` matching `pattern`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern does not start with `/`, contains a parameter
+    /// without a name or a `*` segment which is not the last one.
+    pub fn route<H: Handler>(mut self, method: Method, pattern: &str, handler: H) -> Self {
+        self.routes.push(Route {
+            method,
+            pattern: parse_pattern(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H: Handler>(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<H: Handler>(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<H: Handler>(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<H: Handler>(self, pattern: &str, handler: H) -> Self {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Sets the handler for requests which match no route.
+    pub fn fallback<H: Handler>(mut self, handler: H) -> Self {
+        self.fallback = Box::new(handler);
+        self
+    }
+}
+
+impl Handler for Router {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let segments = req.uri.segments();
+            let mut allowed: Vec<String> = vec![];
+
+            for route in &self.routes {
+                let Some(params) = match_pattern(&route.pattern, &segments) else {
+                    continue;
+                };
+                if route.method == req.method
+                    || (route.method == Method::Get && req.method == Method::Head)
+                {
+                    req.params = params;
+                    return route.handler.handle(req).await;
+                }
+
+                let mut methods = vec![route.method.to_string()];
+                if route.method == Method::Get {
+                    methods.push(Method::Head.to_string());
+                }
+                for m in methods {
+                    if !allowed.contains(&m) {
+                        allowed.push(m);
+                    }
+                }
+            }
+
+            if !allowed.is_empty() {
+                return ResponseBuilder::new()
+                    .status_code(StatusCode::MethodNotAllowed)
+                    .add_header("allow", allowed.join(", "))
+                    .boxed();
+            }
+
+            self.fallback.handle(req).await
+        })
+    }
+}
+
+struct NotFound;
+
+impl Handler for NotFound {
+    fn handle<'a>(&'a self, _: &'a mut Request<'_>) -> BoxFuture<'a, Response> {
+        Box::pin(async {
+            ResponseBuilder::new()
+                .status_code(StatusCode::NotFound)
+                .boxed()
+        })
+    }
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let Some(rest) = pattern.strip_prefix('/') else {
+        panic!("route pattern {pattern:?} does not start with '/'");
+    };
+
+    let segments: Vec<_> = rest
+        .split('/')
+        .map(|s| {
+            if let Some(name) = s.strip_prefix(':') {
+                assert!(!name.is_empty(), "unnamed parameter in route {pattern:?}");
+                Segment::Param(name.to_string())
+            } else if let Some(name) = s.strip_prefix('*') {
+                assert!(!name.is_empty(), "unnamed parameter in route {pattern:?}");
+                Segment::Rest(name.to_string())
+            } else {
+                Segment::Static(s.to_string())
+            }
+        })
+        .collect();
+
+    let rest = segments.iter().position(|s| matches!(s, Segment::Rest(_)));
+    assert!(
+        rest.is_none_or(|i| i == segments.len() - 1),
+        "'*' parameter is not the last segment in route {pattern:?}"
+    );
+
+    segments
+}
+
+/// Matches the path segments against a pattern and returns the values of
+/// its parameters.
+fn match_pattern(pattern: &[Segment], segments: &[String]) -> Option<Vec<(String, String)>> {
+    let mut params = vec![];
+    let mut segments = segments.iter();
+
+    for seg in pattern {
+        match seg {
+            Segment::Rest(name) => {
+                let rest: Vec<_> = segments.map(String::as_str).collect();
+                params.push((name.clone(), rest.join("/")));
+                return Some(params);
+            }
+            Segment::Param(name) => params.push((name.clone(), segments.next()?.clone())),
+            Segment::Static(v) => {
+                if segments.next()? != v {
+                    return None;
+                }
+            }
+        }
+    }
+
+    segments.next().is_none().then_some(params)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> Option<Vec<(String, String)>> {
+        let segments: Vec<_> = path.split('/').skip(1).map(String::from).collect();
+        match_pattern(&parse_pattern(pattern), &segments)
+    }
+
+    fn params(v: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+        Some(
+            v.iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn match_patterns() {
+        assert_eq!(matches("/", "/"), params(&[]));
+        assert_eq!(matches("/api/items", "/api/items"), params(&[]));
+        assert_eq!(matches("/api/items", "/api/items/"), None);
+        assert_eq!(matches("/api/items", "/api"), None);
+        assert_eq!(
+            matches("/api/items/:id", "/api/items/42"),
+            params(&[("id", "42")])
+        );
+        assert_eq!(matches("/api/items/:id", "/api/items"), None);
+        assert_eq!(matches("/api/items/:id", "/api/items/42/x"), None);
+        assert_eq!(
+            matches("/:a/x/:b", "/1/x/2"),
+            params(&[("a", "1"), ("b", "2")])
+        );
+        assert_eq!(
+            matches("/static/*path", "/static/css/app.css"),
+            params(&[("path", "css/app.css")])
+        );
+        assert_eq!(matches("/static/*path", "/static"), params(&[("path", "")]));
+    }
+
+    #[test]
+    #[should_panic(expected = "not the last segment")]
+    fn rest_not_last() {
+        parse_pattern("/*path/x");
+    }
+}
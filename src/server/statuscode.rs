@@ -3,7 +3,7 @@ use std::fmt;
 // Thanks ChatGPT 😂
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
+#[non_exhaustive]
 pub enum StatusCode {
     // 1xx Informational
     Continue,
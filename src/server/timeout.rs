@@ -0,0 +1,144 @@
+use serde::{Deserialize, Deserializer};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWrite,
+    time::{sleep, Instant, Sleep},
+};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+#[non_exhaustive]
+pub struct Timeouts {
+    /// Time a client has to send the complete request head. For the first
+    /// request on a connection, this starts when the connection is
+    /// accepted.
+    #[serde(deserialize_with = "secs")]
+    pub header_read_timeout: Duration,
+    /// Time a client has to send the complete request body.
+    #[serde(deserialize_with = "secs")]
+    pub body_read_timeout: Duration,
+    /// Time a persistent connection is kept open while waiting for the next
+    /// request.
+    #[serde(deserialize_with = "secs")]
+    pub keep_alive_idle_timeout: Duration,
+    /// Time a single write to the client may stall before the connection is
+    /// closed.
+    #[serde(deserialize_with = "secs")]
+    pub write_timeout: Duration,
+    /// Time active connections get to finish their current response on
+    /// shutdown.
+    #[serde(deserialize_with = "secs")]
+    pub drain_timeout: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            header_read_timeout: Duration::from_secs(10),
+            body_read_timeout: Duration::from_secs(60),
+            keep_alive_idle_timeout: Duration::from_secs(15),
+            write_timeout: Duration::from_secs(30),
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Deserializes a duration given in (fractional) seconds.
+fn secs<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(d)?;
+    Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+}
+
+/// Fails writes which make no progress for longer than the timeout with
+/// `ErrorKind::TimedOut`.
+pub struct TimeoutWriter<W> {
+    inner: W,
+    timeout: Duration,
+    sleep: Pin<Box<Sleep>>,
+    armed: bool,
+}
+
+impl<W> TimeoutWriter<W> {
+    pub fn new(inner: W, timeout: Duration) -> Self {
+        Self {
+            inner,
+            timeout,
+            sleep: Box::pin(sleep(timeout)),
+            armed: false,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Polls `op` on the inner writer. While it is pending, the timer runs;
+    /// it is reset once the operation completes.
+    fn poll_with_timeout<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        op: impl FnOnce(Pin<&mut W>, &mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>>
+    where
+        W: Unpin,
+    {
+        if let Poll::Ready(res) = op(Pin::new(&mut self.inner), cx) {
+            self.armed = false;
+            return Poll::Ready(res);
+        }
+
+        if !self.armed {
+            self.sleep.as_mut().reset(Instant::now() + self.timeout);
+            self.armed = true;
+        }
+        ready!(self.sleep.as_mut().poll(cx));
+
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "write timed out",
+        )))
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for TimeoutWriter<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut()
+            .poll_with_timeout(cx, |w, cx| w.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .poll_with_timeout(cx, |w, cx| w.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.get_mut()
+            .poll_with_timeout(cx, |w, cx| w.poll_shutdown(cx))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn write_timeout() {
+        let (client, _server) = tokio::io::duplex(8);
+        let mut w = TimeoutWriter::new(client, Duration::from_millis(50));
+
+        w.write_all(b"12345678").await.unwrap();
+        let err = w.write_all(b"9").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,426 @@
+use super::{
+    handler::{BoxFuture, Handler, Response},
+    request::{Method, Request},
+    response::ResponseBuilder,
+    statuscode::StatusCode,
+    uri::Form,
+};
+use arc_swap::ArcSwap;
+use rustls::{
+    crypto::ring::{default_provider, sign::any_supported_type},
+    pki_types::CertificateDer,
+    server::{danger::ClientCertVerifier, ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    RootCertStore, ServerConfig,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::File,
+    io::{self, BufReader, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+/// Certificates configured in the `[tls]` section of the config file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain used unless one of `sni`
+    /// matches.
+    pub cert: PathBuf,
+    /// PEM file with the private key of `cert`.
+    pub key: PathBuf,
+    #[serde(default)]
+    pub sni: Vec<SniConfig>,
+    pub client_auth: Option<ClientAuthConfig>,
+}
+
+/// A certificate used for clients requesting one of `names` using SNI.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct SniConfig {
+    /// Host names, which may start with a `*.` wildcard label.
+    pub names: Vec<String>,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Client certificate authentication configured in `[tls.client_auth]`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct ClientAuthConfig {
+    /// PEM file with the CA certificates client certificates are verified
+    /// against.
+    pub ca: PathBuf,
+    #[serde(default)]
+    pub mode: ClientAuthMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClientAuthMode {
+    /// Clients may connect without a certificate. If they present one, it
+    /// has to be valid.
+    #[default]
+    Optional,
+    /// The handshake fails for clients without a valid certificate.
+    Required,
+}
+
+/// A default certificate and certificates selected by the server name
+/// requested using SNI.
+#[derive(Debug, Clone)]
+pub struct Certificates {
+    default: Arc<CertifiedKey>,
+    names: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl Certificates {
+    /// Loads the default certificate chain and its private key from PEM
+    /// files.
+    pub fn load<P: AsRef<Path>>(cert: P, key: P) -> io::Result<Self> {
+        Ok(Self {
+            default: Arc::new(load_certified_key(cert.as_ref(), key.as_ref())?),
+            names: HashMap::new(),
+        })
+    }
+
+    /// Loads a certificate used for clients requesting one of `names`.
+    pub fn add<I, S, P>(mut self, names: I, cert: P, key: P) -> io::Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+        P: AsRef<Path>,
+    {
+        let certified = Arc::new(load_certified_key(cert.as_ref(), key.as_ref())?);
+        for name in names {
+            self.names
+                .insert(name.as_ref().to_ascii_lowercase(), certified.clone());
+        }
+        Ok(self)
+    }
+
+    pub fn from_config(cfg: &TlsConfig) -> io::Result<Self> {
+        cfg.sni
+            .iter()
+            .try_fold(Self::load(&cfg.cert, &cfg.key)?, |certs, sni| {
+                certs.add(&sni.names, &sni.cert, &sni.key)
+            })
+    }
+
+    /// Returns the certificate for `name`, matching its first label against
+    /// wildcards if there is none for the exact name.
+    fn get(&self, name: Option<&str>) -> Arc<CertifiedKey> {
+        let Some(name) = name.map(str::to_ascii_lowercase) else {
+            return self.default.clone();
+        };
+        let wildcard = name.split_once('.').map(|(_, rest)| format!("*.{rest}"));
+        self.names
+            .get(&name)
+            .or_else(|| wildcard.and_then(|w| self.names.get(&w)))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+fn context(err: io::Error, path: &Path) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {err}", path.display()))
+}
+
+fn invalid(msg: &str, path: &Path) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("{}: {msg}", path.display()))
+}
+
+/// Reads all certificates from a PEM file, which has to contain at least
+/// one.
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let mut r = BufReader::new(File::open(path).map_err(|e| context(e, path))?);
+    let certs = rustls_pemfile::certs(&mut r)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| context(e, path))?;
+    if certs.is_empty() {
+        return Err(invalid("no certificates found", path));
+    }
+    Ok(certs)
+}
+
+fn load_certified_key(cert: &Path, key: &Path) -> io::Result<CertifiedKey> {
+    let chain = load_certs(cert)?;
+
+    let mut r = BufReader::new(File::open(key).map_err(|e| context(e, key))?);
+    let der = rustls_pemfile::private_key(&mut r)
+        .map_err(|e| context(e, key))?
+        .ok_or_else(|| invalid("no private key found", key))?;
+    let signing_key = any_supported_type(&der).map_err(|e| invalid(&e.to_string(), key))?;
+
+    let certified = CertifiedKey::new(chain, signing_key);
+    certified
+        .keys_match()
+        .map_err(|e| invalid(&e.to_string(), key))?;
+    Ok(certified)
+}
+
+#[derive(Debug)]
+struct Resolver(Arc<ArcSwap<Certificates>>);
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.load().get(hello.server_name()))
+    }
+}
+
+/// Verifies client certificates against a set of CA certificates.
+#[derive(Clone)]
+pub struct ClientAuth(Arc<dyn ClientCertVerifier>);
+
+impl fmt::Debug for ClientAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientAuth").finish_non_exhaustive()
+    }
+}
+
+impl ClientAuth {
+    /// Loads the CA certificates from a PEM file.
+    pub fn load<P: AsRef<Path>>(ca: P, mode: ClientAuthMode) -> io::Result<Self> {
+        let ca = ca.as_ref();
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(ca)? {
+            roots.add(cert).map_err(|e| invalid(&e.to_string(), ca))?;
+        }
+
+        let mut builder = WebPkiClientVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(default_provider()),
+        );
+        if mode == ClientAuthMode::Optional {
+            builder = builder.allow_unauthenticated();
+        }
+        let verifier = builder.build().map_err(|e| invalid(&e.to_string(), ca))?;
+        Ok(Self(verifier))
+    }
+
+    pub fn from_config(cfg: &ClientAuthConfig) -> io::Result<Self> {
+        Self::load(&cfg.ca, cfg.mode)
+    }
+}
+
+/// The verified certificate a client presented in the TLS handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ClientCert {
+    /// The distinguished name of the subject, e.g. `CN=client, O=Example`.
+    pub subject: String,
+    /// The subject alternative names prefixed with their type, e.g.
+    /// `DNS:client.example.com` or `URI:spiffe://example.com/client`.
+    pub san: Vec<String>,
+}
+
+impl ClientCert {
+    fn from_der(der: &CertificateDer<'_>) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let san = match cert.subject_alternative_name().ok()? {
+            Some(ext) => ext
+                .value
+                .general_names
+                .iter()
+                .filter_map(|name| match name {
+                    GeneralName::DNSName(v) => Some(format!("DNS:{v}")),
+                    GeneralName::RFC822Name(v) => Some(format!("email:{v}")),
+                    GeneralName::URI(v) => Some(format!("URI:{v}")),
+                    GeneralName::IPAddress(v) => match *v {
+                        [a, b, c, d] => Some(format!("IP:{}", Ipv4Addr::new(*a, *b, *c, *d))),
+                        _ => <[u8; 16]>::try_from(*v)
+                            .ok()
+                            .map(|v| format!("IP:{}", Ipv6Addr::from(v))),
+                    },
+                    _ => None,
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        Some(Self {
+            subject: cert.subject().to_string(),
+            san,
+        })
+    }
+}
+
+/// Terminates TLS on the connections of a listener, see `Listener::tls`.
+/// Clones share their certificates and client authentication, which can be
+/// replaced while the server is running and apply to connections accepted
+/// afterwards.
+#[derive(Clone)]
+pub struct Tls {
+    certs: Arc<ArcSwap<Certificates>>,
+    config: Arc<ArcSwap<ServerConfig>>,
+}
+
+impl fmt::Debug for Tls {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tls")
+            .field("certs", &self.certs.load())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Tls {
+    /// Creates the TLS configuration without client authentication.
+    pub fn new(certs: Certificates) -> Self {
+        let certs = Arc::new(ArcSwap::from_pointee(certs));
+        let config = server_config(&certs, None);
+        Self {
+            certs,
+            config: Arc::new(ArcSwap::from_pointee(config)),
+        }
+    }
+
+    /// Requests certificates from clients and verifies them using
+    /// `client_auth`.
+    pub fn client_auth(self, client_auth: ClientAuth) -> Self {
+        self.store_client_auth(Some(client_auth));
+        self
+    }
+
+    pub fn from_config(cfg: &TlsConfig) -> io::Result<Self> {
+        let tls = Self::new(Certificates::from_config(cfg)?);
+        Ok(match &cfg.client_auth {
+            Some(c) => tls.client_auth(ClientAuth::from_config(c)?),
+            None => tls,
+        })
+    }
+
+    pub fn store(&self, certs: Certificates) {
+        self.certs.store(Arc::new(certs));
+    }
+
+    pub fn store_client_auth(&self, client_auth: Option<ClientAuth>) {
+        let config = server_config(&self.certs, client_auth);
+        self.config.store(Arc::new(config));
+    }
+
+    /// Performs the handshake and returns the stream along with the
+    /// client's certificate, if it presented one.
+    pub(crate) async fn accept<S>(
+        &self,
+        stream: S,
+    ) -> io::Result<(TlsStream<S>, Option<Arc<ClientCert>>)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let stream = TlsAcceptor::from(self.config.load_full())
+            .accept(stream)
+            .await?;
+        let client_cert = stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(ClientCert::from_der)
+            .map(Arc::new);
+        Ok((stream, client_cert))
+    }
+}
+
+fn server_config(
+    certs: &Arc<ArcSwap<Certificates>>,
+    client_auth: Option<ClientAuth>,
+) -> ServerConfig {
+    let builder = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .expect("default protocol versions are supported");
+    let builder = match client_auth {
+        Some(ClientAuth(verifier)) => builder.with_client_cert_verifier(verifier),
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(Arc::new(Resolver(certs.clone())));
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    config
+}
+
+/// Redirects every request to the same URL using HTTPS, e.g. on the plain
+/// HTTP port of a server which is only meant to be used with TLS.
+#[derive(Debug, Clone, Copy)]
+pub struct HttpsRedirect {
+    port: u16,
+}
+
+impl HttpsRedirect {
+    /// Creates the handler redirecting to HTTPS on `port`.
+    pub fn new(port: u16) -> Self {
+        Self { port }
+    }
+}
+
+impl Default for HttpsRedirect {
+    fn default() -> Self {
+        Self::new(443)
+    }
+}
+
+impl Handler for HttpsRedirect {
+    fn handle<'a>(&'a self, req: &'a mut Request<'_>) -> BoxFuture<'a, Response> {
+        Box::pin(async move {
+            let host = match req.uri.form {
+                Form::Absolute => req.uri.authority.as_deref(),
+                _ => req
+                    .header
+                    .get("host")
+                    .and_then(|v| v.first())
+                    .map(|v| v.as_str()),
+            };
+            let Some(host) = host.filter(|_| req.uri.form != Form::Asterisk) else {
+                return ResponseBuilder::new()
+                    .status_code(StatusCode::BadRequest)
+                    .boxed();
+            };
+
+            let mut location = format!("https://{}", strip_port(host));
+            if self.port != 443 {
+                location += &format!(":{}", self.port);
+            }
+            location += &req.uri.raw_path;
+            if let Some(query) = &req.uri.query {
+                location += &format!("?{query}");
+            }
+
+            // Only GET and HEAD requests may be changed to GET by clients
+            // following a 301, 308 preserves the method and body.
+            let status = match req.method {
+                Method::Get | Method::Head => StatusCode::MovedPermanently,
+                _ => StatusCode::PermanentRedirect,
+            };
+            ResponseBuilder::new()
+                .status_code(status)
+                .add_header("location", location)
+                .boxed()
+        })
+    }
+}
+
+/// Removes the port from a host header value, keeping IPv6 literals intact.
+fn strip_port(host: &str) -> &str {
+    match host.find(']') {
+        Some(end) if host.starts_with('[') => &host[..=end],
+        _ => host.split(':').next().unwrap_or(host),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn host_without_port() {
+        assert_eq!(strip_port("example.com"), "example.com");
+        assert_eq!(strip_port("example.com:8080"), "example.com");
+        assert_eq!(strip_port("[::1]:8080"), "[::1]");
+        assert_eq!(strip_port("[::1]"), "[::1]");
+    }
+}
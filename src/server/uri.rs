@@ -0,0 +1,273 @@
+use super::{error::RequestError, request::Method};
+use std::fmt;
+
+/// The form of a request target as specified in RFC 9112, Section 3.2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Form {
+    /// `/path?query`, used for most requests.
+    Origin,
+    /// `http://host/path?query`, used for requests to proxies.
+    Absolute,
+    /// `host:port`, only used for `CONNECT`.
+    Authority,
+    /// `*`, only used for server-wide `OPTIONS`.
+    Asterisk,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Uri {
+    pub form: Form,
+    pub scheme: Option<String>,
+    pub authority: Option<String>,
+    /// The percent-decoded path. Always starts with `/` for origin-form and
+    /// absolute-form targets and is empty otherwise.
+    pub path: String,
+    /// The path as it was sent by the client.
+    pub raw_path: String,
+    /// The raw query string without the leading `?`.
+    pub query: Option<String>,
+    pub fragment: Option<String>,
+}
+
+impl Uri {
+    pub fn parse(target: &str, method: &Method) -> Result<Self, RequestError> {
+        if target.is_empty() || target.bytes().any(|b| b.is_ascii_control() || b == b' ') {
+            return Err(RequestError::BadRequest("invalid request target"));
+        }
+
+        if *method == Method::Connect {
+            if target.contains(['/', '?', '#']) || !target.contains(':') {
+                return Err(RequestError::BadRequest("CONNECT requires authority-form"));
+            }
+            return Ok(Self::new(Form::Authority, None, Some(target.into())));
+        }
+
+        if target == "*" {
+            if *method != Method::Options {
+                return Err(RequestError::BadRequest(
+                    "asterisk-form is only allowed for OPTIONS",
+                ));
+            }
+            return Ok(Self::new(Form::Asterisk, None, None));
+        }
+
+        let (form, scheme, authority, rest) = if target.starts_with('/') {
+            (Form::Origin, None, None, target)
+        } else if let Some((scheme, rest)) = target.split_once("://") {
+            if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+                return Err(RequestError::BadRequest("unsupported URI scheme"));
+            }
+            let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+            let (authority, rest) = rest.split_at(end);
+            if authority.is_empty() {
+                return Err(RequestError::BadRequest("missing authority"));
+            }
+            (
+                Form::Absolute,
+                Some(scheme.to_lowercase()),
+                Some(authority.to_string()),
+                rest,
+            )
+        } else {
+            return Err(RequestError::BadRequest("invalid request target"));
+        };
+
+        let (rest, fragment) = match rest.split_once('#') {
+            Some((rest, fragment)) => (rest, Some(fragment.to_string())),
+            None => (rest, None),
+        };
+        let (raw_path, query) = match rest.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (rest, None),
+        };
+        let raw_path = match raw_path {
+            "" => "/",
+            v => v,
+        };
+
+        let path = percent_decode(raw_path)
+            .and_then(|v| String::from_utf8(v).ok())
+            .ok_or(RequestError::BadRequest("invalid percent-encoding in path"))?;
+
+        Ok(Self {
+            form,
+            scheme,
+            authority,
+            path,
+            raw_path: raw_path.into(),
+            query,
+            fragment,
+        })
+    }
+
+    fn new(form: Form, scheme: Option<String>, authority: Option<String>) -> Self {
+        Self {
+            form,
+            scheme,
+            authority,
+            path: String::new(),
+            raw_path: String::new(),
+            query: None,
+            fragment: None,
+        }
+    }
+
+    /// Returns the percent-decoded segments of the path. Unlike splitting
+    /// `path`, an encoded `%2F` stays part of its segment.
+    pub fn segments(&self) -> Vec<String> {
+        self.raw_path
+            .split('/')
+            .skip(1)
+            .map(|s| {
+                percent_decode(s)
+                    .map(|v| String::from_utf8_lossy(&v).into_owned())
+                    .unwrap_or_else(|| s.to_string())
+            })
+            .collect()
+    }
+
+    /// Parses the query string as `application/x-www-form-urlencoded`
+    /// key/value pairs.
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        let Some(query) = &self.query else {
+            return vec![];
+        };
+
+        query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (k, v) = p.split_once('=').unwrap_or((p, ""));
+                (decode_form(k), decode_form(v))
+            })
+            .collect()
+    }
+
+    /// Returns the value of the first query parameter named `key`.
+    pub fn query_param(&self, key: &str) -> Option<String> {
+        self.query_pairs()
+            .into_iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v)
+    }
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.form {
+            Form::Asterisk => return write!(f, "*"),
+            Form::Authority => return write!(f, "{}", self.authority.as_deref().unwrap_or("")),
+            Form::Absolute => write!(
+                f,
+                "{}://{}",
+                self.scheme.as_deref().unwrap_or("http"),
+                self.authority.as_deref().unwrap_or("")
+            )?,
+            Form::Origin => {}
+        }
+
+        write!(f, "{}", self.raw_path)?;
+        if let Some(query) = &self.query {
+            write!(f, "?{query}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes `%XX` escapes. Returns `None` for malformed escapes.
+pub fn percent_decode(v: &str) -> Option<Vec<u8>> {
+    let bytes = v.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3)?;
+            let hex = std::str::from_utf8(hex).ok()?;
+            res.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            res.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    Some(res)
+}
+
+/// Encodes everything except unreserved characters and a few sub-delims
+/// which are safe within a path segment.
+pub fn percent_encode(v: &str) -> String {
+    let mut res = String::with_capacity(v.len());
+    for b in v.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&b) {
+            res.push(b as char);
+        } else {
+            res.push_str(&format!("%{b:02X}"));
+        }
+    }
+    res
+}
+
+fn decode_form(v: &str) -> String {
+    let v = v.replace('+', " ");
+    match percent_decode(&v) {
+        Some(decoded) => String::from_utf8_lossy(&decoded).into_owned(),
+        None => v,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn origin_form() {
+        let uri = Uri::parse("/my%20file.txt?v=3&q=a+b%26c&flag#top", &Method::Get).unwrap();
+        assert_eq!(uri.form, Form::Origin);
+        assert_eq!(uri.path, "/my file.txt");
+        assert_eq!(uri.raw_path, "/my%20file.txt");
+        assert_eq!(uri.fragment.as_deref(), Some("top"));
+        assert_eq!(
+            uri.query_pairs(),
+            [
+                ("v".into(), "3".into()),
+                ("q".into(), "a b&c".into()),
+                ("flag".into(), "".into())
+            ]
+        );
+        assert_eq!(uri.query_param("v").as_deref(), Some("3"));
+
+        let uri = Uri::parse("/a%2Fb/c", &Method::Get).unwrap();
+        assert_eq!(uri.path, "/a/b/c");
+        assert_eq!(uri.segments(), ["a/b", "c"]);
+
+        assert_eq!(percent_encode("my file/ü.txt"), "my%20file%2F%C3%BC.txt");
+        assert!(Uri::parse("/%zz", &Method::Get).is_err());
+        assert!(Uri::parse("/%ff", &Method::Get).is_err());
+        assert!(Uri::parse("index.html", &Method::Get).is_err());
+    }
+
+    #[test]
+    fn other_forms() {
+        let uri = Uri::parse("HTTP://example.com:8080?x=1", &Method::Get).unwrap();
+        assert_eq!(uri.form, Form::Absolute);
+        assert_eq!(uri.scheme.as_deref(), Some("http"));
+        assert_eq!(uri.authority.as_deref(), Some("example.com:8080"));
+        assert_eq!(uri.path, "/");
+        assert_eq!(uri.query.as_deref(), Some("x=1"));
+
+        let uri = Uri::parse("example.com:443", &Method::Connect).unwrap();
+        assert_eq!(uri.form, Form::Authority);
+        assert_eq!(uri.authority.as_deref(), Some("example.com:443"));
+        assert!(Uri::parse("/", &Method::Connect).is_err());
+
+        let uri = Uri::parse("*", &Method::Options).unwrap();
+        assert_eq!(uri.form, Form::Asterisk);
+        assert!(Uri::parse("*", &Method::Get).is_err());
+
+        assert!(Uri::parse("ftp://example.com/", &Method::Get).is_err());
+    }
+}
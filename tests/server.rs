@@ -0,0 +1,1674 @@
+use http_server::{
+    handler_fn, middleware_fn, BasicAuth, Certificates, ClientAuth, ClientAuthMode, FileServer,
+    HttpsRedirect, Limits, Listener, Middleware, MimeConfig, MimeTypes, Peer, RequireClientCert,
+    ResponseBuilder, Router, Server, SetHeaders, StatusCode, SymlinkPolicy, Timeouts, Tls,
+    UnixSocketOptions,
+};
+use rustls::{
+    crypto::ring::default_provider,
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName},
+};
+use std::{
+    future::pending,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::sleep,
+};
+use tokio_rustls::TlsConnector;
+
+async fn spawn_server() -> String {
+    spawn_server_at("content").await
+}
+
+async fn spawn_server_at(root: impl Into<PathBuf>) -> String {
+    let root = root.into();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder()
+        .content_root(root)
+        .implicit_index(true)
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+    addr.to_string()
+}
+
+/// Creates an empty directory in the system temp dir, unique per test.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("http-server-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes a raw request to a new connection, closes the write half and
+/// returns everything the server sends until it closes the connection.
+async fn raw_request(addr: &str, req: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(req).await.unwrap();
+    stream.shutdown().await.unwrap();
+    let mut res = vec![];
+    let _ = stream.read_to_end(&mut res).await;
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+/// Like `raw_request`, but keeps the write half open, so the response
+/// only ends if the server closes the connection on its own.
+async fn raw_request_open(addr: &str, req: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(req).await.unwrap();
+    let mut res = vec![];
+    tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut res))
+        .await
+        .expect("server did not close the connection")
+        .unwrap();
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+#[tokio::test]
+async fn integration_test() {
+    let addr = "127.0.0.1:18735";
+
+    tokio::spawn(async move {
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        Server::builder()
+            .content_root("content")
+            .implicit_index(true)
+            .build(listener)
+            .listen(pending())
+            .await
+    });
+    sleep(Duration::from_millis(100)).await;
+
+    let res = reqwest::get(format!("http://{addr}")).await.unwrap();
+    assert!(res.status().is_success());
+    assert_eq!(
+        res.headers()
+            .get("Content-Type")
+            .map(|v| v.to_str().unwrap()),
+        Some("text/html; charset=utf-8")
+    );
+
+    let mut index_contents = String::new();
+    File::open("content/index.html")
+        .await
+        .unwrap()
+        .read_to_string(&mut index_contents)
+        .await
+        .unwrap();
+
+    assert_eq!(res.text().await.unwrap(), index_contents);
+
+    let res = reqwest::get(format!("http://{addr}/does-not-exist"))
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+    assert_eq!(res.text().await.unwrap(), "Not Found");
+}
+
+#[tokio::test]
+async fn head_request() {
+    let addr = spawn_server().await;
+    let client = reqwest::Client::new();
+
+    let get = client
+        .get(format!("http://{addr}/seal.webp"))
+        .send()
+        .await
+        .unwrap();
+    let get_len = get.headers().get("Content-Length").cloned();
+    let get_type = get.headers().get("Content-Type").cloned();
+
+    let res = client
+        .head(format!("http://{addr}/seal.webp"))
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    assert_eq!(res.headers().get("Content-Length").cloned(), get_len);
+    assert_eq!(res.headers().get("Content-Type").cloned(), get_type);
+    assert_eq!(res.bytes().await.unwrap().len(), 0);
+
+    let res = client
+        .head(format!("http://{addr}/does-not-exist"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+    assert_eq!(res.bytes().await.unwrap().len(), 0);
+}
+
+#[tokio::test]
+async fn conditional_get() {
+    let addr = spawn_server().await;
+    let client = reqwest::Client::new();
+    let url = format!("http://{addr}/seal.webp");
+
+    let res = client.get(&url).send().await.unwrap();
+    let etag = res.headers().get("ETag").unwrap().clone();
+    let last_modified = res.headers().get("Last-Modified").unwrap().clone();
+
+    let res = client
+        .get(&url)
+        .header("If-None-Match", etag.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 304);
+    assert_eq!(res.headers().get("ETag"), Some(&etag));
+    assert_eq!(res.bytes().await.unwrap().len(), 0);
+
+    let res = client
+        .get(&url)
+        .header("If-Modified-Since", last_modified)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 304);
+
+    let res = client
+        .get(&url)
+        .header("If-Match", "\"something-else\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 412);
+}
+
+#[tokio::test]
+async fn range_requests() {
+    let addr = spawn_server().await;
+    let client = reqwest::Client::new();
+    let url = format!("http://{addr}/index.html");
+    let contents = std::fs::read("content/index.html").unwrap();
+    let len = contents.len();
+
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(
+        res.headers()
+            .get("Accept-Ranges")
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        "bytes"
+    );
+    let etag = res.headers().get("ETag").unwrap().clone();
+
+    let res = client
+        .get(&url)
+        .header("Range", "bytes=0-9")
+        .header("If-Range", etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 206);
+    assert_eq!(
+        res.headers()
+            .get("Content-Range")
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        format!("bytes 0-9/{len}")
+    );
+    assert_eq!(res.bytes().await.unwrap(), contents[0..10]);
+
+    let res = client
+        .get(&url)
+        .header("Range", "bytes=0-1,-2")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 206);
+    let content_type = res.headers().get("Content-Type").unwrap().to_str().unwrap();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap()
+        .to_string();
+    let body = res.text().await.unwrap();
+    assert!(body.contains(&format!("Content-Range: bytes 0-1/{len}\r\n\r\n<!")));
+    assert!(body.contains(&format!(
+        "Content-Range: bytes {}-{}/{len}",
+        len - 2,
+        len - 1
+    )));
+    assert!(body.ends_with(&format!("\r\n--{boundary}--\r\n")));
+
+    let res = client
+        .get(&url)
+        .header("Range", "bytes=0-9")
+        .header("If-Range", "\"stale\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.bytes().await.unwrap(), contents);
+
+    let res = client
+        .get(&url)
+        .header("Range", format!("bytes={len}-"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 416);
+    assert_eq!(
+        res.headers()
+            .get("Content-Range")
+            .unwrap()
+            .to_str()
+            .unwrap(),
+        format!("bytes */{len}")
+    );
+}
+
+#[tokio::test]
+async fn request_bodies() {
+    let addr = spawn_server().await;
+
+    let res = raw_request(
+        &addr,
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 11\r\n\r\nhello world\
+          POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+          5\r\nhello\r\n0\r\n\r\n\
+          HEAD / HTTP/1.1\r\nHost: a\r\n\r\n\
+          GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n",
+    )
+    .await;
+    let statuses: Vec<_> = res
+        .match_indices("HTTP/1.1 ")
+        .map(|(i, _)| &res[i..i + res[i..].find("\r\n").unwrap()])
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            "HTTP/1.1 405 Method Not Allowed",
+            "HTTP/1.1 405 Method Not Allowed",
+            "HTTP/1.1 200 OK",
+            "HTTP/1.1 400 Bad Request",
+        ]
+    );
+
+    let res = raw_request(
+        &addr,
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1073741824\r\n\r\n",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+}
+
+#[tokio::test]
+async fn malformed_requests() {
+    let addr = spawn_server().await;
+
+    let res = raw_request(&addr, b"garbage\r\n\r\n").await;
+    assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    assert!(res.contains("Connection: close\r\n"));
+
+    let res = raw_request(&addr, b"GET / HTTP/2.0\r\n\r\n").await;
+    assert!(res.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+
+    let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+    let res = raw_request(&addr, long.as_bytes()).await;
+    assert!(res.starts_with("HTTP/1.1 414 URI Too Long\r\n"));
+
+    let res = raw_request(&addr, b"BREW / HTTP/1.1\r\nHost: a\r\n\r\n").await;
+    assert!(res.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+}
+
+#[tokio::test]
+async fn request_targets() {
+    let root = temp_dir("request-targets");
+    std::fs::write(root.join("my file.txt"), "spaces").unwrap();
+    std::fs::write(root.join("index.html"), "index").unwrap();
+    let addr = spawn_server_at(&root).await;
+
+    let res = reqwest::get(format!("http://{addr}/my%20file.txt"))
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.text().await.unwrap(), "spaces");
+
+    let res = reqwest::get(format!("http://{addr}/index.html?v=3"))
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.text().await.unwrap(), "index");
+
+    let res = raw_request(
+        &addr,
+        format!(
+            "GET http://{addr}/index.html HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n"
+        )
+        .as_bytes(),
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.ends_with("index"));
+
+    let res = raw_request(&addr, b"GET /%zz HTTP/1.1\r\nHost: a\r\n\r\n").await;
+    assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+    let res = raw_request(
+        &addr,
+        b"OPTIONS * HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn path_traversal() {
+    let root = temp_dir("path-traversal");
+    std::fs::create_dir(root.join("public")).unwrap();
+    std::fs::write(root.join("secret.txt"), "secret").unwrap();
+    std::fs::write(root.join("public/a.txt"), "a").unwrap();
+    let addr = spawn_server_at(root.join("public")).await;
+
+    for target in [
+        "/../secret.txt",
+        "/%2e%2e/secret.txt",
+        "/%2E%2E%2Fsecret.txt",
+        "/sub/../../secret.txt",
+        "/..\\secret.txt",
+        "/..%5csecret.txt",
+        "/a.txt%00.html",
+    ] {
+        let req = format!("GET {target} HTTP/1.1\r\nHost: a\r\n\r\n");
+        let res = raw_request(&addr, req.as_bytes()).await;
+        assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{target}");
+        assert!(!res.contains("secret"), "{target}");
+    }
+
+    let res = raw_request(&addr, b"GET /x/../a.txt HTTP/1.1\r\nHost: a\r\n\r\n").await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlink_policy() {
+    let root = temp_dir("symlink-policy");
+    std::fs::write(root.join("target.txt"), "target").unwrap();
+    std::os::unix::fs::symlink(root.join("target.txt"), root.join("link.txt")).unwrap();
+
+    for (policy, status) in [
+        (SymlinkPolicy::Follow, 200),
+        (SymlinkPolicy::IfOwnerMatches, 200),
+        (SymlinkPolicy::Deny, 403),
+    ] {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::builder()
+            .content_root(&root)
+            .symlinks(policy)
+            .build(listener);
+        tokio::spawn(async move { server.listen(pending()).await });
+
+        let res = reqwest::get(format!("http://{addr}/link.txt"))
+            .await
+            .unwrap();
+        assert_eq!(res.status().as_u16(), status, "{policy:?}");
+    }
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn directory_listing() {
+    let root = temp_dir("directory-listing");
+    std::fs::create_dir(root.join("sub dir")).unwrap();
+    std::fs::write(root.join("b.txt"), "bb").unwrap();
+    std::fs::write(root.join("a <&>.txt"), "aaaa").unwrap();
+    std::fs::write(root.join("sub dir/c.txt"), "c").unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder()
+        .content_root(&root)
+        .implicit_index(true)
+        .directory_listing(true)
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+    let client = reqwest::Client::new();
+
+    let res = client.get(format!("http://{addr}/")).send().await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.headers().get("Vary").unwrap(), "Accept");
+    let html = res.text().await.unwrap();
+    assert!(html.contains("<a href=\"/sub%20dir/\">sub dir/</a>"));
+    assert!(html.contains("<a href=\"/a%20%3C&%3E.txt\">a &lt;&amp;&gt;.txt</a>"));
+    assert!(html.find("sub dir/").unwrap() < html.find("a &lt;").unwrap());
+    assert!(html.find("a &lt;").unwrap() < html.find("b.txt").unwrap());
+
+    let res = client
+        .get(format!("http://{addr}/?sort=size&order=desc"))
+        .header("Accept", "text/html;q=0.5, application/json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        res.headers().get("Content-Type").unwrap(),
+        "application/json"
+    );
+    let json: serde_json::Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(json["path"], "/");
+    let names: Vec<_> = json["entries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["name"].as_str().unwrap(), e["type"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            ("sub dir", "directory"),
+            ("a <&>.txt", "file"),
+            ("b.txt", "file")
+        ]
+    );
+    assert_eq!(json["entries"][1]["size"], 4);
+
+    let res = client
+        .get(format!("http://{addr}/sub%20dir"))
+        .send()
+        .await
+        .unwrap();
+    let html = res.text().await.unwrap();
+    assert!(html.contains("<a href=\"/sub%20dir/c.txt\">c.txt</a>"));
+    assert!(html.contains("<a href=\"/\">../</a>"));
+
+    let addr = spawn_server_at(&root).await;
+    let res = reqwest::get(format!("http://{addr}/sub%20dir/"))
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+/// Splits a raw response into head and body and removes the chunked
+/// transfer-coding from the body.
+fn dechunk(res: &[u8]) -> (String, Vec<u8>) {
+    let split = res.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8_lossy(&res[..split]).into_owned();
+
+    let mut body = vec![];
+    let mut rest = &res[split..];
+    loop {
+        let eol = rest.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size = usize::from_str_radix(std::str::from_utf8(&rest[..eol]).unwrap(), 16).unwrap();
+        if size == 0 {
+            break;
+        }
+        body.extend_from_slice(&rest[eol + 2..eol + 2 + size]);
+        rest = &rest[eol + 2 + size + 2..];
+    }
+
+    (head, body)
+}
+
+#[tokio::test]
+async fn compression() {
+    use async_compression::tokio::bufread::{BrotliDecoder, GzipDecoder, ZstdDecoder};
+
+    let root = temp_dir("compression");
+    let text = "hello compression ".repeat(200);
+    std::fs::write(root.join("a.txt"), &text).unwrap();
+    std::fs::write(root.join("small.txt"), "small").unwrap();
+    let addr = spawn_server_at(&root).await;
+
+    let request = |path: &str, accept_encoding: &str| {
+        format!("GET {path} HTTP/1.1\r\nHost: a\r\nAccept-Encoding: {accept_encoding}\r\n\r\n")
+    };
+    let send = |req: String| {
+        let addr = addr.clone();
+        async move {
+            let mut stream = TcpStream::connect(&addr).await.unwrap();
+            stream.write_all(req.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+            let mut res = vec![];
+            let _ = stream.read_to_end(&mut res).await;
+            res
+        }
+    };
+
+    let mut etags = vec![];
+    for (coding, accept) in [
+        ("br", "gzip, br, zstd"),
+        ("zstd", "zstd, gzip;q=0.5"),
+        ("gzip", "gzip"),
+    ] {
+        let (head, body) = dechunk(&send(request("/a.txt", accept)).await);
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        assert!(head.contains(&format!("Content-Encoding: {coding}\r\n")));
+        assert!(head.contains("Vary: Accept-Encoding\r\n"));
+        assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(body.len() < text.len());
+
+        let mut decoded = String::new();
+        match coding {
+            "br" => {
+                BrotliDecoder::new(&body[..])
+                    .read_to_string(&mut decoded)
+                    .await
+            }
+            "zstd" => {
+                ZstdDecoder::new(&body[..])
+                    .read_to_string(&mut decoded)
+                    .await
+            }
+            _ => {
+                GzipDecoder::new(&body[..])
+                    .read_to_string(&mut decoded)
+                    .await
+            }
+        }
+        .unwrap();
+        assert_eq!(decoded, text);
+
+        let etag = head
+            .lines()
+            .find_map(|l| l.strip_prefix("Etag: ").or(l.strip_prefix("ETag: ")))
+            .unwrap()
+            .to_string();
+        assert!(etag.ends_with(&format!("-{coding}\"")));
+        etags.push(etag);
+    }
+
+    let req = format!(
+        "GET /a.txt HTTP/1.1\r\nHost: a\r\nAccept-Encoding: gzip\r\nIf-None-Match: {}\r\n\r\n",
+        etags[2]
+    );
+    let res = String::from_utf8(send(req).await).unwrap();
+    assert!(res.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+    // The compressed representation has no known length, so none is sent.
+    assert!(!res.contains("Content-Length"), "{res}");
+    assert!(!res.contains("Transfer-Encoding"), "{res}");
+
+    let res = String::from_utf8(send(request("/a.txt", "identity")).await).unwrap();
+    assert!(!res.contains("Content-Encoding"));
+    assert!(res.contains("Vary: Accept-Encoding\r\n"));
+    assert!(res.ends_with(&text));
+
+    let res = String::from_utf8(send(request("/small.txt", "gzip")).await).unwrap();
+    assert!(!res.contains("Content-Encoding"));
+    assert!(!res.contains("Vary"));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn precompressed_sidecars() {
+    let root = temp_dir("sidecars");
+    std::fs::write(root.join("app.js"), "console.log(1)").unwrap();
+    std::fs::write(root.join("app.js.br"), "br-data").unwrap();
+    std::fs::write(root.join("app.js.gz"), "gz-data").unwrap();
+    let addr = spawn_server_at(&root).await;
+
+    let res = raw_request(
+        &addr,
+        b"GET /app.js HTTP/1.1\r\nHost: a\r\n\r\n\
+          GET /app.js HTTP/1.1\r\nHost: a\r\nAccept-Encoding: gzip, br\r\n\r\n\
+          HEAD /app.js HTTP/1.1\r\nHost: a\r\nAccept-Encoding: br;q=0.1, gzip\r\n\r\n\
+          GET /app.js HTTP/1.1\r\nHost: a\r\nAccept-Encoding: zstd\r\n\r\n\
+          GET /app.js HTTP/1.1\r\nHost: a\r\nAccept-Encoding: br\r\nRange: bytes=0-6\r\n\r\n",
+    )
+    .await;
+    let responses: Vec<_> = res.split("HTTP/1.1 ").skip(1).collect();
+    assert_eq!(responses.len(), 5);
+
+    let identity = responses[0];
+    assert!(!identity.contains("Content-Encoding"));
+    assert!(identity.contains("Vary: Accept-Encoding\r\n"));
+    assert!(identity.ends_with("\r\n\r\nconsole.log(1)"));
+    let etag = identity
+        .lines()
+        .find_map(|l| l.strip_prefix("Etag: "))
+        .unwrap()
+        .trim_end_matches('"');
+
+    let br = responses[1];
+    assert!(br.contains("Content-Encoding: br\r\n"));
+    assert!(br.contains("Content-Type: application/javascript; charset=utf-8\r\n"));
+    assert!(br.contains("Content-Length: 7\r\n"));
+    assert!(br.contains(&format!("Etag: {etag}-br\"\r\n")));
+    assert!(br.ends_with("\r\n\r\nbr-data"));
+
+    let gzip = responses[2];
+    assert!(gzip.contains("Content-Encoding: gzip\r\n"));
+    assert!(gzip.ends_with("\r\n\r\n"));
+
+    assert!(!responses[3].contains("Content-Encoding"));
+    assert!(responses[3].ends_with("\r\n\r\nconsole.log(1)"));
+
+    assert!(responses[4].starts_with("206 Partial Content\r\n"));
+    assert!(responses[4].ends_with("\r\n\r\nconsole"));
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn mime_types() {
+    let root = temp_dir("mime-types");
+    std::fs::write(root.join("data.json"), "{}").unwrap();
+    std::fs::write(root.join("font.woff2"), "wOF2").unwrap();
+    std::fs::write(root.join("notes.md"), "# notes").unwrap();
+    std::fs::write(root.join("image"), b"\x89PNG\r\n\x1a\n\0\0\0\0").unwrap();
+    std::fs::write(root.join("blob.xyz"), b"\0\x01\x02\x03").unwrap();
+
+    let mut cfg = MimeConfig::default();
+    cfg.default = Some("application/octet-stream".into());
+    cfg.sniff = true;
+    cfg.types
+        .insert("md".into(), "text/markdown; charset=utf-8".into());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder()
+        .content_root(&root)
+        .mime_types(MimeTypes::from_config(&cfg).unwrap())
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+
+    for (path, ty) in [
+        ("data.json", "application/json"),
+        ("font.woff2", "font/woff2"),
+        ("notes.md", "text/markdown; charset=utf-8"),
+        ("image", "image/png"),
+        ("blob.xyz", "application/octet-stream"),
+    ] {
+        let res = reqwest::get(format!("http://{addr}/{path}")).await.unwrap();
+        assert_eq!(res.headers().get("Content-Type").unwrap(), ty, "{path}");
+    }
+
+    let res = reqwest::get(format!("http://{addr}/image")).await.unwrap();
+    assert_eq!(res.bytes().await.unwrap().len(), 12);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[tokio::test]
+async fn connection_persistence() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let mut limits = Limits::default();
+    limits.max_requests_per_connection = 3;
+    let server = Server::builder()
+        .content_root("content")
+        .implicit_index(true)
+        .limits(limits)
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+
+    let res = raw_request_open(&addr, b"HEAD / HTTP/1.0\r\n\r\nHEAD / HTTP/1.0\r\n\r\n").await;
+    assert!(res.starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(res.contains("Connection: close\r\n"));
+    assert_eq!(res.matches("HTTP/1.0 ").count(), 1);
+
+    let res = raw_request_open(
+        &addr,
+        b"HEAD / HTTP/1.0\r\nConnection: keep-alive\r\n\r\nHEAD / HTTP/1.0\r\n\r\n",
+    )
+    .await;
+    let responses: Vec<_> = res.split("HTTP/1.0 200 OK\r\n").skip(1).collect();
+    assert_eq!(responses.len(), 2);
+    assert!(responses[0].contains("Connection: keep-alive\r\n"));
+    assert!(responses[1].contains("Connection: close\r\n"));
+
+    let res = raw_request_open(
+        &addr,
+        b"HEAD / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\nHEAD / HTTP/1.1\r\nHost: a\r\n\r\n",
+    )
+    .await;
+    assert_eq!(res.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+    assert!(res.contains("Connection: close\r\n"));
+
+    let res = raw_request_open(&addr, &b"HEAD / HTTP/1.1\r\nHost: a\r\n\r\n".repeat(5)).await;
+    let responses: Vec<_> = res.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+    assert_eq!(responses.len(), 3);
+    assert!(!responses[1].contains("Connection:"));
+    assert!(responses[2].contains("Connection: close\r\n"));
+}
+
+#[tokio::test]
+async fn timeouts() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let mut timeouts = Timeouts::default();
+    timeouts.header_read_timeout = Duration::from_millis(300);
+    timeouts.body_read_timeout = Duration::from_millis(300);
+    timeouts.keep_alive_idle_timeout = Duration::from_secs(1);
+    let server = Server::builder()
+        .content_root("content")
+        .implicit_index(true)
+        .timeouts(timeouts)
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+
+    // Idle connections are closed without a response.
+    assert_eq!(raw_request_open(&addr, b"").await, "");
+
+    let res = raw_request_open(&addr, b"GET / HTTP/1.1\r\nHost: a\r\n").await;
+    assert!(res.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    assert!(res.contains("Connection: close\r\n"));
+
+    let res = raw_request_open(&addr, b"HEAD / HTTP/1.1\r\nHost: a\r\n\r\n").await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.contains("Keep-Alive: timeout=1\r\n"));
+
+    let res = raw_request_open(
+        &addr,
+        b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nab",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+}
+
+#[tokio::test]
+async fn graceful_shutdown() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let mut timeouts = Timeouts::default();
+    timeouts.drain_timeout = Duration::from_millis(500);
+    let server = Server::builder()
+        .content_root("content")
+        .implicit_index(true)
+        .timeouts(timeouts)
+        .build(listener);
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        server
+            .listen(async {
+                let _ = rx.await;
+            })
+            .await
+    });
+
+    // An idle keep-alive connection.
+    let mut idle = TcpStream::connect(&addr).await.unwrap();
+    idle.write_all(b"HEAD / HTTP/1.1\r\nHost: a\r\n\r\n")
+        .await
+        .unwrap();
+    let mut buf = [0; 1024];
+    assert!(idle.read(&mut buf).await.unwrap() > 0);
+
+    // A connection in the middle of sending a request.
+    let mut active = TcpStream::connect(&addr).await.unwrap();
+    active.write_all(b"HEAD / HTTP/1.1\r\n").await.unwrap();
+
+    // A connection which never completes its request.
+    let mut stalled = TcpStream::connect(&addr).await.unwrap();
+    stalled.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    tx.send(()).unwrap();
+    sleep(Duration::from_millis(100)).await;
+
+    let mut rest = vec![];
+    idle.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+
+    active.write_all(b"Host: a\r\n\r\n").await.unwrap();
+    let mut res = vec![];
+    active.read_to_end(&mut res).await.unwrap();
+    let res = String::from_utf8(res).unwrap();
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.contains("Connection: close\r\n"));
+
+    tokio::time::timeout(Duration::from_secs(2), server)
+        .await
+        .expect("server did not stop after the drain timeout")
+        .unwrap()
+        .unwrap();
+    let mut rest = vec![];
+    let _ = stalled.read_to_end(&mut rest).await;
+    assert!(rest.is_empty());
+
+    assert!(TcpStream::connect(&addr).await.is_err());
+}
+
+#[tokio::test]
+async fn reload_settings() {
+    let old_root = temp_dir("reload-old");
+    let new_root = temp_dir("reload-new");
+    std::fs::write(old_root.join("a.txt"), "old").unwrap();
+    std::fs::write(new_root.join("b.txt"), "new").unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = Server::builder().content_root(&old_root).build(listener);
+    let handle = server.settings_handle();
+    tokio::spawn(async move { server.listen(pending()).await });
+
+    // Settings are swapped between requests of a persistent connection.
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /a.txt HTTP/1.1\r\nHost: a\r\n\r\n")
+        .await
+        .unwrap();
+    let mut res = String::new();
+    let mut buf = [0; 1024];
+    while !res.ends_with("\r\n\r\nold") {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "connection closed early: {res}");
+        res.push_str(std::str::from_utf8(&buf[..n]).unwrap());
+    }
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let mut settings = (*handle.load()).clone();
+    settings.content_root = new_root;
+    handle.store(settings);
+
+    stream
+        .write_all(
+            b"GET /a.txt HTTP/1.1\r\nHost: a\r\n\r\n\
+              GET /b.txt HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut res = vec![];
+    stream.read_to_end(&mut res).await.unwrap();
+    let res = String::from_utf8(res).unwrap();
+    assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    assert_eq!(res.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+    assert!(res.ends_with("\r\n\r\nnew"));
+}
+
+#[tokio::test]
+async fn router() {
+    let router = Router::new()
+        .get(
+            "/api/items/:id",
+            handler_fn(|req| {
+                Box::pin(async move {
+                    let id = req.param("id").unwrap_or_default();
+                    ResponseBuilder::new()
+                        .add_header("content-type", "text/plain")
+                        .text(format!("item {id}"))
+                        .boxed()
+                })
+            }),
+        )
+        .post(
+            "/api/items",
+            handler_fn(|req| {
+                Box::pin(async move {
+                    match req.body.bytes().await {
+                        Ok(body) => ResponseBuilder::new()
+                            .status_code(StatusCode::Created)
+                            .text(String::from_utf8_lossy(&body))
+                            .boxed(),
+                        Err(err) => ResponseBuilder::new()
+                            .status_code(err.status_code().unwrap_or(StatusCode::BadRequest))
+                            .boxed(),
+                    }
+                })
+            }),
+        )
+        .fallback(FileServer);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Server::builder()
+        .content_root("content")
+        .implicit_index(true)
+        .handler(router)
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+    let client = reqwest::Client::new();
+
+    let res = client
+        .get(format!("http://{addr}/api/items/a%20b"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.headers().get("content-type").unwrap(), "text/plain");
+    assert_eq!(res.text().await.unwrap(), "item a b");
+
+    let res = raw_request(&addr, b"HEAD /api/items/42 HTTP/1.1\r\nHost: a\r\n\r\n").await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(res.contains("Content-Length: 7\r\n"));
+    assert!(res.ends_with("\r\n\r\n"));
+
+    let res = client
+        .delete(format!("http://{addr}/api/items/42"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 405);
+    assert_eq!(res.headers().get("allow").unwrap(), "GET, HEAD");
+
+    let res = client
+        .post(format!("http://{addr}/api/items"))
+        .body("hello")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 201);
+    assert_eq!(res.text().await.unwrap(), "hello");
+
+    // Everything else is served by the file server.
+    let res = client.get(format!("http://{addr}/")).send().await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(
+        res.headers().get("content-type").unwrap(),
+        "text/html; charset=utf-8"
+    );
+
+    let res = client
+        .get(format!("http://{addr}/api/items"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 405);
+    let res = client
+        .get(format!("http://{addr}/api/nope"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn expect_continue() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Server::builder()
+        .handler(handler_fn(|req| {
+            Box::pin(async move {
+                if req.uri.path != "/echo" {
+                    return ResponseBuilder::new()
+                        .status_code(StatusCode::NotFound)
+                        .boxed();
+                }
+                let body = req.body.bytes().await.unwrap();
+                ResponseBuilder::new()
+                    .text(String::from_utf8(body).unwrap())
+                    .boxed()
+            })
+        }))
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+
+    // The client is asked for the body once the handler reads it.
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    stream
+        .write_all(
+            b"POST /echo HTTP/1.1\r\nhost: localhost\r\ncontent-length: 5\r\n\
+              expect: 100-continue\r\nconnection: close\r\n\r\n",
+        )
+        .await
+        .unwrap();
+    let mut buf = [0; 64];
+    let n = stream.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"HTTP/1.1 100 Continue\r\n\r\n");
+    stream.write_all(b"hello").await.unwrap();
+    let mut res = String::new();
+    stream.read_to_string(&mut res).await.unwrap();
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+    assert!(res.ends_with("\r\n\r\nhello"), "{res}");
+
+    // A response not needing the body is sent without asking for it, and
+    // the connection is closed as the body never arrives.
+    let res = raw_request_open(
+        &addr,
+        b"POST /other HTTP/1.1\r\nhost: localhost\r\ncontent-length: 5\r\n\
+          expect: 100-continue\r\n\r\n",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"), "{res}");
+    assert!(!res.contains("100 Continue"), "{res}");
+    assert!(res.contains("\r\nConnection: close\r\n"), "{res}");
+}
+
+#[tokio::test]
+async fn middleware() {
+    // Appends `name` to the `X-Trace` header of the request on the way in
+    // and of the response on the way out.
+    fn trace(name: &'static str) -> impl Middleware {
+        middleware_fn(move |req, next| {
+            Box::pin(async move {
+                req.header.insert("x-trace", name);
+                let trace = req.header.get("x-trace").unwrap().join(",");
+                let res = next.run(req).await;
+                let out = res.get_header("x-trace").map(|v| v.join(","));
+                let out = out.map_or(format!("{trace}|{name}"), |v| format!("{v},{name}"));
+                res.set_header("x-trace", out)
+            })
+        })
+    }
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Server::builder()
+        .content_root("content")
+        .implicit_index(true)
+        .layer(trace("a"))
+        .layer(SetHeaders::new([("x-frame-options", "DENY")]))
+        .layer(BasicAuth::new("test", [("admin", "secret")]).paths(["/private"]))
+        .layer(trace("b"))
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+    let client = reqwest::Client::new();
+
+    let res = client.get(format!("http://{addr}/")).send().await.unwrap();
+    assert_eq!(res.status().as_u16(), 200);
+    assert_eq!(res.headers().get("x-trace").unwrap(), "a,b|b,a");
+    assert_eq!(res.headers().get("x-frame-options").unwrap(), "DENY");
+
+    // Authentication short-circuits the inner layers and the handler, but
+    // outer layers still see the response.
+    let res = client
+        .get(format!("http://{addr}/private/x"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 401);
+    assert_eq!(
+        res.headers().get("www-authenticate").unwrap(),
+        "Basic realm=\"test\", charset=\"UTF-8\""
+    );
+    assert_eq!(res.headers().get("x-trace").unwrap(), "a|a");
+    assert_eq!(res.headers().get("x-frame-options").unwrap(), "DENY");
+
+    let res = client
+        .get(format!("http://{addr}/private/x"))
+        .basic_auth("admin", Some("wrong"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 401);
+
+    // Paths are matched the way the file server resolves them.
+    for path in ["//private/x", "/x/../private/x"] {
+        let req = format!("GET {path} HTTP/1.1\r\nhost: localhost\r\n\r\n");
+        let res = raw_request(&addr, req.as_bytes()).await;
+        assert!(
+            res.starts_with("HTTP/1.1 401 Unauthorized\r\n"),
+            "{path}: {res}"
+        );
+    }
+    let res = raw_request(
+        &addr,
+        b"GET /../private/x HTTP/1.1\r\nhost: localhost\r\n\r\n",
+    )
+    .await;
+    assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{res}");
+
+    let res = client
+        .get(format!("http://{addr}/private/x"))
+        .basic_auth("admin", Some("secret"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status().as_u16(), 404);
+    assert_eq!(res.headers().get("x-trace").unwrap(), "a,b|b,a");
+}
+
+#[tokio::test]
+async fn serve_connection() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = Server::builder()
+        .content_root("content")
+        .implicit_index(true)
+        .build(listener);
+    let (mut client, stream) = tokio::io::duplex(64 * 1024);
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let conn = tokio::spawn(async move {
+        server
+            .serve_connection(stream, Peer::Unknown, async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+    });
+
+    // Requests on a persistent connection are served until shutdown, which
+    // closes the idle connection.
+    let mut buf = vec![0; 64 * 1024];
+    for _ in 0..2 {
+        client
+            .write_all(b"HEAD / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let n = client.read(&mut buf).await.unwrap();
+        let res = String::from_utf8_lossy(&buf[..n]);
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+        assert!(res.ends_with("\r\n\r\n"), "{res}");
+    }
+
+    shutdown_tx.send(()).unwrap();
+    conn.await.unwrap().unwrap();
+    assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let dir = temp_dir("unix-socket");
+    let path = dir.join("server.sock");
+    let addr = format!("unix:{}", path.display());
+
+    // A socket left behind by a previous process is replaced.
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    let mut options = UnixSocketOptions::default();
+    options.mode = Some(0o600);
+    let listener = Listener::bind(&addr, &options).await.unwrap();
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o600
+    );
+
+    let server = Server::builder()
+        .handler(handler_fn(|req| {
+            Box::pin(async move { ResponseBuilder::new().text(req.peer().to_string()).boxed() })
+        }))
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+
+    // A socket in use is not.
+    let err = Listener::bind(&addr, &options).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+
+    let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut res = String::new();
+    stream.read_to_string(&mut res).await.unwrap();
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+
+    let uid = std::fs::metadata(&dir).unwrap().uid();
+    let peer = res.split("\r\n\r\n").nth(1).unwrap();
+    assert!(peer.starts_with(&format!("uid={uid},")), "{peer}");
+    assert!(
+        peer.contains(&format!(",pid={}", std::process::id())),
+        "{peer}"
+    );
+}
+
+#[tokio::test]
+async fn multiple_listeners() {
+    let root = temp_dir("multiple-listeners");
+    std::fs::write(root.join("index.html"), "other root").unwrap();
+
+    let (a, b, c) = (
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+    );
+    let addrs = [a.local_addr(), b.local_addr(), c.local_addr()].map(|a| a.unwrap().to_string());
+
+    let builder = Server::builder()
+        .content_root("content")
+        .implicit_index(true);
+    let mut server = builder.clone().build(a);
+    server.add_listener(b, None);
+    let other = server.add_listener(c, Some(builder.content_root(&root).into_settings()));
+    assert_eq!(other.load().content_root, root);
+
+    let (shutdown_tx, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(async move {
+        server
+            .listen(async {
+                let _ = shutdown_rx.await;
+            })
+            .await
+    });
+
+    let index = std::fs::read_to_string("content/index.html").unwrap();
+    for (addr, body) in addrs.iter().zip([index.as_str(), &index, "other root"]) {
+        let res = reqwest::get(format!("http://{addr}/")).await.unwrap();
+        assert_eq!(res.text().await.unwrap(), body);
+    }
+
+    // All listeners are closed on shutdown.
+    shutdown_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    for addr in &addrs {
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn socket_activation() {
+    use std::{
+        net::TcpListener,
+        os::{fd::AsRawFd, unix::process::CommandExt},
+        process::{Command, Stdio},
+    };
+
+    let dir = temp_dir("socket-activation");
+    let admin = dir.join("admin");
+    std::fs::create_dir(&admin).unwrap();
+    std::fs::write(admin.join("index.html"), "admin").unwrap();
+    let cfg = dir.join("config.toml");
+    std::fs::write(
+        &cfg,
+        format!(
+            r#"
+            [server]
+            content_root = "content"
+            address = "127.0.0.1:1"
+            implicit_index = true
+
+            [[listener]]
+            address = "127.0.0.1:2"
+            content_root = "{}"
+            fd_name = "admin"
+            "#,
+            admin.display()
+        ),
+    )
+    .unwrap();
+
+    // Passed in the opposite order of the config to check the mapping by
+    // name. The addresses in the config are never bound.
+    let admin_sock = TcpListener::bind("127.0.0.1:0").unwrap();
+    let http_sock = TcpListener::bind("127.0.0.1:0").unwrap();
+    let fds = [admin_sock.as_raw_fd(), http_sock.as_raw_fd()];
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(r#"LISTEN_PID=$$ exec "$0" "$1""#)
+        .arg(env!("CARGO_BIN_EXE_http-server"))
+        .arg(&cfg)
+        .env("LISTEN_FDS", "2")
+        .env("LISTEN_FDNAMES", "admin:http")
+        .stdout(Stdio::null());
+    // SAFETY: Only async-signal-safe functions are called after fork.
+    unsafe {
+        cmd.pre_exec(move || {
+            // Moving the sockets out of the way first keeps them from being
+            // overwritten if they already are at 3 or 4. `dup2` clears the
+            // close-on-exec flag only for a different target descriptor.
+            let mut moved = fds;
+            for fd in &mut moved {
+                *fd = libc::fcntl(*fd, libc::F_DUPFD, 10);
+                if *fd == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            for (i, fd) in moved.into_iter().enumerate() {
+                if libc::dup2(fd, 3 + i as i32) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let mut child = cmd.spawn().unwrap();
+
+    let get = |addr: std::net::SocketAddr| {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        std::io::Write::write_all(
+            &mut stream,
+            b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+        )
+        .unwrap();
+        let mut res = String::new();
+        std::io::Read::read_to_string(&mut stream, &mut res).unwrap();
+        res
+    };
+    let http = get(http_sock.local_addr().unwrap());
+    let admin = get(admin_sock.local_addr().unwrap());
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let index = std::fs::read_to_string("content/index.html").unwrap();
+    assert!(http.starts_with("HTTP/1.1 200 OK\r\n"), "{http}");
+    assert!(http.ends_with(&index), "{http}");
+    assert!(admin.ends_with("\r\n\r\nadmin"), "{admin}");
+}
+
+/// Writes a self-signed certificate for `names` and its key to `dir` and
+/// returns their paths with the certificate.
+fn self_signed(dir: &Path, names: &[&str]) -> (PathBuf, PathBuf, CertificateDer<'static>) {
+    let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+    let certified = rcgen::generate_simple_self_signed(names.clone()).unwrap();
+    let (cert, key) = (
+        dir.join(format!("{}.pem", names[0])),
+        dir.join(format!("{}.key.pem", names[0])),
+    );
+    std::fs::write(&cert, certified.cert.pem()).unwrap();
+    std::fs::write(&key, certified.key_pair.serialize_pem()).unwrap();
+    (cert, key, certified.cert.der().clone())
+}
+
+/// Connects using TLS with `server_name` and sends a request. Returns the
+/// response along with the certificate and ALPN protocol of the server.
+async fn tls_request(
+    addr: &str,
+    server_name: &str,
+    roots: &[&CertificateDer<'static>],
+) -> (String, CertificateDer<'static>, Option<Vec<u8>>) {
+    let mut root_store = rustls::RootCertStore::empty();
+    for cert in roots {
+        root_store.add((*cert).clone()).unwrap();
+    }
+    let mut config = rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let name = ServerName::try_from(server_name.to_string()).unwrap();
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await
+        .unwrap();
+    let (_, conn) = stream.get_ref();
+    let cert = conn.peer_certificates().unwrap()[0].clone();
+    let alpn = conn.alpn_protocol().map(<[u8]>::to_vec);
+
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut res = vec![];
+    stream.read_to_end(&mut res).await.unwrap();
+    (String::from_utf8_lossy(&res).into_owned(), cert, alpn)
+}
+
+#[tokio::test]
+async fn tls() {
+    let dir = temp_dir("tls");
+    let (cert, key, default_cert) = self_signed(&dir, &["localhost"]);
+    let (sni_cert, sni_key, example_cert) = self_signed(&dir, &["example.test", "*.wild.test"]);
+
+    let certs = Certificates::load(&cert, &key)
+        .unwrap()
+        .add(["example.test", "*.wild.test"], &sni_cert, &sni_key)
+        .unwrap();
+    let tls = Tls::new(certs);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Server::builder()
+        .content_root("content")
+        .implicit_index(true)
+        .build(Listener::from(listener).tls(tls.clone()));
+    tokio::spawn(async move { server.listen(pending()).await });
+
+    let roots = [&default_cert, &example_cert];
+    let (res, cert, alpn) = tls_request(&addr, "localhost", &roots).await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+    assert_eq!(cert, default_cert);
+    assert_eq!(alpn.as_deref(), Some(&b"http/1.1"[..]));
+
+    for name in ["example.test", "EXAMPLE.test", "a.wild.test"] {
+        let (_, cert, _) = tls_request(&addr, name, &roots).await;
+        assert_eq!(cert, example_cert, "{name}");
+    }
+
+    // Replaced certificates are used for new connections.
+    let (new_cert, new_key, new_default) = self_signed(&temp_dir("tls-new"), &["localhost"]);
+    tls.store(Certificates::load(&new_cert, &new_key).unwrap());
+    let (res, cert, _) = tls_request(&addr, "localhost", &[&new_default]).await;
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+    assert_eq!(cert, new_default);
+
+    // A key not matching the certificate is rejected.
+    let err = Certificates::load(&new_cert, &sni_key).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn https_redirect() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Server::builder()
+        .handler(HttpsRedirect::new(8443))
+        .build(listener);
+    tokio::spawn(async move { server.listen(pending()).await });
+
+    let res = raw_request(
+        &addr,
+        b"GET /a%20b?c=d HTTP/1.1\r\nhost: example.test:8080\r\n\r\n",
+    )
+    .await;
+    assert!(
+        res.starts_with("HTTP/1.1 301 Moved Permanently\r\n"),
+        "{res}"
+    );
+    assert!(
+        res.contains("\r\nLocation: https://example.test:8443/a%20b?c=d\r\n"),
+        "{res}"
+    );
+
+    let res = raw_request(
+        &addr,
+        b"POST /form HTTP/1.1\r\nhost: [::1]\r\ncontent-length: 0\r\n\r\n",
+    )
+    .await;
+    assert!(
+        res.starts_with("HTTP/1.1 308 Permanent Redirect\r\n"),
+        "{res}"
+    );
+    assert!(
+        res.contains("\r\nLocation: https://[::1]:8443/form\r\n"),
+        "{res}"
+    );
+}
+
+/// The redirect listener of the binary runs none of the configured
+/// middleware, which would otherwise ask for credentials over plain HTTP.
+#[tokio::test]
+async fn https_redirect_without_middleware() {
+    let dir = temp_dir("https-redirect-middleware");
+    let (cert, key, _) = self_signed(&dir, &["localhost"]);
+    let free_port = || {
+        let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        l.local_addr().unwrap().port()
+    };
+    let (https_port, http_port) = (free_port(), free_port());
+    let cfg = dir.join("config.toml");
+    std::fs::write(
+        &cfg,
+        format!(
+            r#"
+            [server]
+            content_root = "content"
+            address = "127.0.0.1:{https_port}"
+
+            [tls]
+            cert = "{}"
+            key = "{}"
+            redirect = "127.0.0.1:{http_port}"
+
+            [[middleware]]
+            type = "basic-auth"
+            users = {{ admin = "secret" }}
+            "#,
+            cert.display(),
+            key.display()
+        ),
+    )
+    .unwrap();
+
+    let mut child = tokio::process::Command::new(env!("CARGO_BIN_EXE_http-server"))
+        .arg(&cfg)
+        .stdout(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+
+    let addr = format!("127.0.0.1:{http_port}");
+    for _ in 0..50 {
+        if TcpStream::connect(&addr).await.is_ok() {
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    let res = raw_request(&addr, b"GET /private HTTP/1.1\r\nhost: localhost\r\n\r\n").await;
+    child.kill().await.unwrap();
+
+    assert!(
+        res.starts_with("HTTP/1.1 301 Moved Permanently\r\n"),
+        "{res}"
+    );
+    assert!(
+        res.contains(&format!(
+            "\r\nLocation: https://localhost:{https_port}/private\r\n"
+        )),
+        "{res}"
+    );
+    assert!(!res.contains("Www-Authenticate"), "{res}");
+}
+
+/// Connects using TLS, optionally presenting a client certificate, and
+/// requests `path`. Fails if the server rejects the handshake.
+async fn client_cert_request(
+    addr: &str,
+    root: &CertificateDer<'static>,
+    client: Option<(CertificateDer<'static>, PrivateKeyDer<'static>)>,
+    path: &str,
+) -> std::io::Result<String> {
+    let mut root_store = rustls::RootCertStore::empty();
+    root_store.add(root.clone()).unwrap();
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(root_store);
+    let config = match client {
+        Some((cert, key)) => config.with_client_auth_cert(vec![cert], key).unwrap(),
+        None => config.with_no_client_auth(),
+    };
+
+    let tcp = TcpStream::connect(addr).await?;
+    let name = ServerName::try_from("localhost").unwrap();
+    let mut stream = TlsConnector::from(Arc::new(config))
+        .connect(name, tcp)
+        .await?;
+    let req = format!("GET {path} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n");
+    stream.write_all(req.as_bytes()).await?;
+    let mut res = vec![];
+    stream.read_to_end(&mut res).await?;
+    Ok(String::from_utf8_lossy(&res).into_owned())
+}
+
+#[tokio::test]
+async fn client_auth() {
+    use rcgen::{
+        BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair,
+    };
+
+    let dir = temp_dir("client-auth");
+    let (cert, key, server_cert) = self_signed(&dir, &["localhost"]);
+
+    let ca_key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec![]).unwrap();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    params
+        .distinguished_name
+        .push(DnType::CommonName, "Test CA");
+    let ca = params.self_signed(&ca_key).unwrap();
+    let ca_path = dir.join("ca.pem");
+    std::fs::write(&ca_path, ca.pem()).unwrap();
+
+    let client_key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec!["client.test".into()]).unwrap();
+    params.distinguished_name.push(DnType::CommonName, "client");
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let client_cert = params.signed_by(&client_key, &ca, &ca_key).unwrap();
+    let client = || {
+        let key = PrivatePkcs8KeyDer::from(client_key.serialize_der());
+        Some((client_cert.der().clone(), key.into()))
+    };
+
+    // A certificate signed by another CA.
+    let other_key = KeyPair::generate().unwrap();
+    let mut params = CertificateParams::new(vec!["other.test".into()]).unwrap();
+    params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+    let other_cert = params.self_signed(&other_key).unwrap();
+    let other = || {
+        let key = PrivatePkcs8KeyDer::from(other_key.serialize_der());
+        Some((other_cert.der().clone(), key.into()))
+    };
+
+    let tls = Tls::new(Certificates::load(&cert, &key).unwrap())
+        .client_auth(ClientAuth::load(&ca_path, ClientAuthMode::Optional).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Server::builder()
+        .handler(handler_fn(|req| {
+            Box::pin(async move {
+                let body = match req.client_cert() {
+                    Some(cert) => format!("{} {}", cert.subject, cert.san.join(",")),
+                    None => "anonymous".into(),
+                };
+                ResponseBuilder::new().text(body).boxed()
+            })
+        }))
+        .layer(RequireClientCert::new().paths(["/internal"]))
+        .build(Listener::from(listener).tls(tls.clone()));
+    tokio::spawn(async move { server.listen(pending()).await });
+
+    let res = client_cert_request(&addr, &server_cert, None, "/")
+        .await
+        .unwrap();
+    assert!(res.ends_with("\r\n\r\nanonymous"), "{res}");
+    // Paths are matched the way the file server resolves them.
+    for path in [
+        "/internal/a",
+        "//internal/a",
+        "/./internal/a",
+        "/pub/../internal/a",
+    ] {
+        let res = client_cert_request(&addr, &server_cert, None, path)
+            .await
+            .unwrap();
+        assert!(
+            res.starts_with("HTTP/1.1 403 Forbidden\r\n"),
+            "{path}: {res}"
+        );
+    }
+    let res = client_cert_request(&addr, &server_cert, None, "/../internal/a")
+        .await
+        .unwrap();
+    assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{res}");
+
+    let res = client_cert_request(&addr, &server_cert, client(), "/internal/a")
+        .await
+        .unwrap();
+    assert!(res.starts_with("HTTP/1.1 200 OK\r\n"), "{res}");
+    assert!(res.ends_with("\r\n\r\nCN=client DNS:client.test"), "{res}");
+
+    // An untrusted certificate fails the handshake even when optional.
+    assert!(client_cert_request(&addr, &server_cert, other(), "/")
+        .await
+        .is_err());
+
+    tls.store_client_auth(Some(
+        ClientAuth::load(&ca_path, ClientAuthMode::Required).unwrap(),
+    ));
+    assert!(client_cert_request(&addr, &server_cert, None, "/")
+        .await
+        .is_err());
+    let res = client_cert_request(&addr, &server_cert, client(), "/")
+        .await
+        .unwrap();
+    assert!(res.ends_with("\r\n\r\nCN=client DNS:client.test"), "{res}");
+
+    // Without client authentication, no certificate is requested.
+    tls.store_client_auth(None);
+    let res = client_cert_request(&addr, &server_cert, client(), "/internal/a")
+        .await
+        .unwrap();
+    assert!(res.starts_with("HTTP/1.1 403 Forbidden\r\n"), "{res}");
+}